@@ -0,0 +1,143 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::STORAGE;
+
+// Changes are not committed right away, but queued and committed
+// together (with a combined message) once the window has passed
+// since the first queued change, so bulk operations don't produce dozens of commits.
+//
+// The queue only keeps the messages and the timer - the commit itself is made by
+// Storage::commit_queued with the storage's repository, under the STORAGE lock.
+// That way it never sees a half done move or swap, and never races
+// another commit for the index.
+
+struct QueueState {
+    messages: Vec<String>,
+    first_queued: Option<Instant>,
+    window: Duration,
+    //error of the last background commit, reported by the next flush
+    last_error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    wakeup: Condvar,
+}
+
+pub(crate) struct CommitQueue {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for CommitQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.shared.state.lock().unwrap();
+        write!(
+            f,
+            "CommitQueue({} pending, window {:?})",
+            state.messages.len(),
+            state.window
+        )
+    }
+}
+
+impl CommitQueue {
+    pub(crate) fn new(window: Duration) -> CommitQueue {
+        CommitQueue {
+            shared: Arc::new(Shared {
+                state: Mutex::new(QueueState {
+                    messages: Vec::new(),
+                    first_queued: None,
+                    window,
+                    last_error: None,
+                }),
+                wakeup: Condvar::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn set_window(&self, window: Duration) {
+        self.shared.state.lock().unwrap().window = window;
+        self.shared.wakeup.notify_all();
+    }
+
+    /// queue a commit with msg, of whatever the data path looks like by then
+    pub(crate) fn push(&self, msg: &str) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.messages.push(msg.to_string());
+        state.first_queued.get_or_insert_with(Instant::now);
+        self.shared.wakeup.notify_all();
+    }
+
+    /// the queued messages - the caller commits them
+    pub(crate) fn take_messages(&self) -> Vec<String> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.first_queued = None;
+        std::mem::take(&mut state.messages)
+    }
+
+    pub(crate) fn take_error(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().last_error.take()
+    }
+
+    pub(crate) fn set_error(&self, error: String) {
+        self.shared.state.lock().unwrap().last_error = Some(error);
+    }
+
+    /// block until the window since the first queued change has passed
+    fn wait_until_due(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            match state.first_queued {
+                None => state = self.shared.wakeup.wait(state).unwrap(),
+                Some(first) => {
+                    let due = first + state.window;
+                    let now = Instant::now();
+                    if now >= due {
+                        return;
+                    }
+                    state = self.shared.wakeup.wait_timeout(state, due - now).unwrap().0;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn combined_message(messages: &[String]) -> String {
+    match messages.len() {
+        0 => "Checkpoint".to_string(),
+        1 => messages[0].clone(),
+        n => format!(
+            "{} (+{} more)\n\n{}",
+            messages[0],
+            n - 1,
+            messages
+                .iter()
+                .map(|m| format!("- {}", m))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+/// commit the queued changes once their window has passed
+pub(crate) fn spawn_background_commits() {
+    let queue = CommitQueue {
+        shared: STORAGE
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .commit_queue()
+            .shared
+            .clone(),
+    };
+    thread::spawn(move || loop {
+        //the queue's own lock is released by now - Storage takes it again
+        queue.wait_until_due();
+        STORAGE.get().unwrap().lock().unwrap().commit_queued();
+    });
+}
@@ -0,0 +1,94 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    path::Path,
+    process::{Child, Command},
+};
+
+use crate::settings::{EditorSettings, TerminalSettings};
+
+// Starting the external editor / terminal, as configured in [editor] / [terminal].
+
+/// The full command line to edit file at line: terminal wrapper + editor command,
+/// with {file} and {line} filled in
+pub(crate) fn editor_command_line(
+    settings: &EditorSettings,
+    file: &Path,
+    line: usize,
+) -> Vec<String> {
+    let file = file.to_string_lossy();
+    let mut args: Vec<String> = settings.terminal.clone();
+    let mut has_file = false;
+    for part in settings.command.iter() {
+        has_file |= part.contains("{file}");
+        args.push(
+            part.replace("{file}", &file)
+                .replace("{line}", &line.to_string()),
+        );
+    }
+    if !has_file {
+        args.push(file.to_string());
+    }
+    args
+}
+
+fn spawn(args: &[String], folder: Option<&Path>) -> Result<Child> {
+    let (program, rest) = match args.split_first() {
+        Some(x) => x,
+        None => bail!("empty command"),
+    };
+    let mut command = Command::new(program);
+    command.args(rest);
+    if let Some(folder) = folder {
+        command.current_dir(folder);
+    }
+    command
+        .spawn()
+        .with_context(|| format!("Failed to start '{}' - check settings.toml", args.join(" ")))
+}
+
+pub(crate) fn launch_editor(settings: &EditorSettings, file: &Path, line: usize) -> Result<Child> {
+    spawn(&editor_command_line(settings, file, line), None)
+}
+
+pub(crate) fn launch_terminal(settings: &TerminalSettings, folder: &Path) -> Result<Child> {
+    spawn(&settings.command, Some(folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::WaitMode;
+
+    fn command_line(command: &[&str], terminal: &[&str]) -> Vec<String> {
+        let settings = EditorSettings {
+            command: command.iter().map(|x| x.to_string()).collect(),
+            terminal: terminal.iter().map(|x| x.to_string()).collect(),
+            wait: WaitMode::Exit,
+        };
+        editor_command_line(&settings, Path::new("/tmp/my node.adoc"), 7)
+    }
+
+    #[test]
+    fn file_and_line_are_filled_in() {
+        assert_eq!(
+            command_line(&["nvim", "+{line}", "{file}"], &["kitty", "--"]),
+            ["kitty", "--", "nvim", "+7", "/tmp/my node.adoc"]
+        );
+        assert_eq!(
+            command_line(&["code", "--goto", "{file}:{line}"], &[]),
+            ["code", "--goto", "/tmp/my node.adoc:7"]
+        );
+    }
+
+    #[test]
+    fn file_is_appended_without_placeholder() {
+        assert_eq!(
+            command_line(&["gedit", "+{line}"], &[]),
+            ["gedit", "+7", "/tmp/my node.adoc"]
+        );
+        assert_eq!(
+            command_line(&["hx"], &["alacritty", "-e"]),
+            ["alacritty", "-e", "hx", "/tmp/my node.adoc"]
+        );
+    }
+}
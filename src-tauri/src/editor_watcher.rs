@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+use tauri::Manager;
+
+use crate::settings::WaitMode;
+use crate::storage::parse_raw_content;
+use crate::RUNTIME_STATE;
+
+// One inotify instance (and thread) for the temp files of all open editors.
+// Watches are on the folders of the temp files (editors like to replace
+// files instead of writing them), added when an edit starts and removed
+// when it ends (editor_ended).
+
+//collect the writes of one save (or a burst of autosaves) into one event
+const DEBOUNCE_MS: u64 = 100;
+
+struct Session {
+    //event sent to the frontend with (path_for_js, content) on each save
+    msg_to_js: Option<&'static str>,
+    path_for_js: String,
+    wait: WaitMode,
+}
+
+#[derive(Default)]
+struct State {
+    //temp file -> session
+    sessions: HashMap<PathBuf, Session>,
+    //watched folder -> (watch, number of sessions in it)
+    folders: HashMap<PathBuf, (WatchDescriptor, usize)>,
+}
+
+struct EditorWatcher {
+    watches: Mutex<Watches>,
+    state: Mutex<State>,
+}
+
+static EDITOR_WATCHER: OnceCell<EditorWatcher> = OnceCell::new();
+
+fn watcher() -> Result<&'static EditorWatcher> {
+    EDITOR_WATCHER.get_or_try_init(|| {
+        let inotify = Inotify::init().context("inotify init failed")?;
+        let watches = inotify.watches();
+        thread::spawn(move || watch_loop(inotify));
+        Ok(EditorWatcher {
+            watches: Mutex::new(watches),
+            state: Mutex::new(State::default()),
+        })
+    })
+}
+
+/// Start watching an editor temp file
+pub(crate) fn watch(
+    temp_file: &Path,
+    msg_to_js: Option<&'static str>,
+    path_for_js: &str,
+    wait: WaitMode,
+) -> Result<()> {
+    let watcher = watcher()?;
+    let folder = temp_file
+        .parent()
+        .context("temp file without a folder")?
+        .to_path_buf();
+    let mut state = watcher.state.lock().unwrap();
+    if state.sessions.contains_key(temp_file) {
+        unwatch_locked(watcher, &mut state, temp_file);
+    }
+    match state.folders.get_mut(&folder) {
+        Some((_, count)) => *count += 1,
+        None => {
+            let wd = watcher
+                .watches
+                .lock()
+                .unwrap()
+                .add(
+                    &folder,
+                    WatchMask::CLOSE_WRITE | WatchMask::DELETE | WatchMask::MOVED_TO,
+                )
+                .with_context(|| format!("failed to watch {:?}", folder))?;
+            state.folders.insert(folder, (wd, 1));
+        }
+    }
+    state.sessions.insert(
+        temp_file.to_path_buf(),
+        Session {
+            msg_to_js,
+            path_for_js: path_for_js.to_string(),
+            wait,
+        },
+    );
+    Ok(())
+}
+
+/// Stop watching - the edit is over
+pub(crate) fn unwatch(temp_file: &Path) {
+    if let Some(watcher) = EDITOR_WATCHER.get() {
+        let mut state = watcher.state.lock().unwrap();
+        unwatch_locked(watcher, &mut state, temp_file);
+    }
+}
+
+fn unwatch_locked(watcher: &EditorWatcher, state: &mut State, temp_file: &Path) {
+    if state.sessions.remove(temp_file).is_none() {
+        return;
+    }
+    let folder = match temp_file.parent() {
+        Some(folder) => folder.to_path_buf(),
+        None => return,
+    };
+    let last = match state.folders.get_mut(&folder) {
+        Some((_, count)) => {
+            *count -= 1;
+            *count == 0
+        }
+        None => false,
+    };
+    if last {
+        if let Some((wd, _)) = state.folders.remove(&folder) {
+            //fails if the folder is gone already - that's fine
+            watcher.watches.lock().unwrap().remove(wd).ok();
+        }
+    }
+}
+
+fn read_events(
+    inotify: &mut Inotify,
+    buffer: &mut [u8],
+    blocking: bool,
+) -> Vec<(WatchDescriptor, EventMask, Option<std::ffi::OsString>)> {
+    let events = if blocking {
+        inotify.read_events_blocking(buffer)
+    } else {
+        inotify.read_events(buffer)
+    };
+    match events {
+        Ok(events) => events
+            .map(|e| (e.wd, e.mask, e.name.map(|x| x.to_os_string())))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn watch_loop(mut inotify: Inotify) {
+    let mut buffer = [0; 4096];
+    loop {
+        let mut events = read_events(&mut inotify, &mut buffer, true);
+        thread::sleep(Duration::from_millis(DEBOUNCE_MS));
+        loop {
+            let more = read_events(&mut inotify, &mut buffer, false);
+            if more.is_empty() {
+                break;
+            }
+            events.extend(more);
+        }
+        let watcher = match EDITOR_WATCHER.get() {
+            Some(w) => w,
+            None => continue,
+        };
+        let mut changed: HashSet<PathBuf> = HashSet::new();
+        {
+            let mut state = watcher.state.lock().unwrap();
+            for (wd, mask, name) in events {
+                if mask.contains(EventMask::IGNORED) {
+                    //folder removed
+                    state.folders.retain(|_, (w, _)| *w != wd);
+                    continue;
+                }
+                let folder = state
+                    .folders
+                    .iter()
+                    .find(|(_, (w, _))| *w == wd)
+                    .map(|(folder, _)| folder.clone());
+                if let (Some(folder), Some(name)) = (folder, name) {
+                    let file = folder.join(name);
+                    if state.sessions.contains_key(&file) {
+                        changed.insert(file);
+                    }
+                }
+            }
+        }
+        for file in changed {
+            temp_file_changed(watcher, &file);
+        }
+    }
+}
+
+fn temp_file_changed(watcher: &EditorWatcher, file: &Path) {
+    //we also get an event when the temp file finally gets removed
+    let content = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    let (msg_to_js, path_for_js, wait) = {
+        let state = watcher.state.lock().unwrap();
+        match state.sessions.get(file) {
+            Some(s) => (s.msg_to_js, s.path_for_js.clone(), s.wait),
+            None => return,
+        }
+    };
+    let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    if let Some(msg_to_js) = msg_to_js {
+        lock.app_handle
+            .emit_all(msg_to_js, (&path_for_js, parse_raw_content(&content)))
+            .ok();
+    }
+    if wait == WaitMode::Save {
+        for entry in lock.open_editors.iter_mut() {
+            if entry.temp_file == file {
+                entry.saved = true;
+            }
+        }
+        drop(lock);
+        crate::editor_ended();
+    }
+}
@@ -3,40 +3,66 @@
     windows_subsystem = "windows"
 )]
 
+mod commit_queue;
+mod editor;
+mod editor_watcher;
 mod mail;
+mod merge;
 mod openai;
+mod query;
+mod search;
+mod settings;
 mod storage;
+mod sync;
+mod watcher;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
 use chrono::Datelike;
-use inotify::{Inotify, WatchMask};
 use once_cell::sync::OnceCell;
 use serde::Serialize;
 use signal_hook::iterator::Signals;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     env,
     path::PathBuf,
-    sync::{Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
     thread::{self},
 };
-use storage::{Node, Storage, TreePath};
+use storage::{parse_raw_content, Node, Storage, TreePath};
 use tauri::Manager;
 
+//lock order: STORAGE before RUNTIME_STATE -
+//never lock STORAGE while holding RUNTIME_STATE
 static STORAGE: OnceCell<Mutex<Storage>> = OnceCell::new();
 
 pub struct OpenEditor {
+    //stable id of this editor session
+    id: u64,
     path: String,
     temp_file: PathBuf,
     org_content: String,
     process: std::process::Child,
     window_title: String,
     remove_after: bool,
+    wait: settings::WaitMode,
+    //the temp file was written - ends the edit with WaitMode::Save
+    saved: bool,
+    //for node edits: the node as it was when the editor was opened
+    base: Option<storage::EditBase>,
 }
 
+static NEXT_EDITOR_SESSION: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 struct RuntimeState {
     open_editors: Vec<OpenEditor>,
+    //other processes we started (terminals), reaped by the supervisor
+    other_children: Vec<std::process::Child>,
+    //problems found while starting up, for the first window to show
+    startup_messages: Vec<String>,
     app_handle: tauri::AppHandle,
     notmuch_db: mail::MailStore,
 }
@@ -45,6 +71,8 @@ impl RuntimeState {
     fn new(handle: tauri::AppHandle, notmuch_db: mail::MailStore) -> RuntimeState {
         RuntimeState {
             open_editors: Vec::new(),
+            other_children: Vec::new(),
+            startup_messages: Vec::new(),
             app_handle: handle,
             notmuch_db,
         }
@@ -84,11 +112,13 @@ impl From<anyhow::Error> for TauriError {
     }
 }
 
+//locked after STORAGE, see there
 static RUNTIME_STATE: OnceCell<Mutex<RuntimeState>> = OnceCell::new();
 
 impl std::fmt::Debug for OpenEditor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("OpenEditor")
+            .field("id", &self.id)
             .field("path", &self.path)
             .field("temp_file", &self.temp_file.file_name())
             .field("process", &self.process.id())
@@ -97,19 +127,19 @@ impl std::fmt::Debug for OpenEditor {
 }
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-fn parse_raw_content(raw_content: &str) -> &str {
-    match raw_content.split_once("\n--\n") {
-        Some((_header, content)) => content.trim(),
-        None => &raw_content,
-    }
-}
-
 #[derive(Debug, Clone, Serialize)]
 pub(crate) struct NodeForJSInner {
     pub path: String,
+    pub id: String,
     pub header: storage::Header,
     pub raw: String,
     pub tags: Vec<String>,
+    pub links: Vec<String>,
+    pub properties: BTreeMap<String, storage::PropertyValue>,
+    //from ':alias:', usable as '@alias' instead of the path
+    pub aliases: Vec<String>,
+    //both versions, if the node has git conflict markers
+    pub conflict: Option<storage::Conflict>,
     //children: Vec<Node>,
 }
 
@@ -119,37 +149,70 @@ pub(crate) struct NodeForJS {
     pub levels: Vec<(String, String)>,
     pub children: Vec<NodeForJSInner>,
     pub tags: Vec<String>,
+    //(path, title) of nodes linking here
+    pub backlinks: Vec<(String, String)>,
 }
 
 impl From<&Node> for NodeForJSInner {
     fn from(node: &Node) -> Self {
         NodeForJSInner {
             path: node.path.to_human(),
+            id: node.id.clone(),
             header: node.header.clone(),
             raw: node.raw.clone(),
             tags: node.get_tags(),
+            links: node.links.clone(),
+            properties: node.properties.clone(),
+            aliases: node.aliases(),
+            conflict: node.conflict.clone(),
         }
     }
 }
 #[tauri::command]
 fn get_node(path: &str) -> TauriResult<NodeForJS> {
     let s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = s.resolve_path(path)?;
     let node: Option<NodeForJSInner> = s.get_node(&path).map(|x| x.into());
     let children: Vec<NodeForJSInner> = s.children_for(&path).iter().map(|x| (*x).into()).collect();
     let tags = node.as_ref().map_or_else(|| Vec::new(), |x| x.tags.clone());
+    let backlinks = s
+        .get_backlinks(&path)
+        .iter()
+        .map(|x| (x.path.to_human(), x.header.title.clone()))
+        .collect();
     Ok(NodeForJS {
         node,
         levels: s.levels(&path),
         children,
         tags,
+        backlinks,
     })
 }
 
+#[tauri::command]
+fn get_backlinks(path: &str) -> TauriResult<Vec<NodeForJSInner>> {
+    let s = STORAGE.get().unwrap().lock().unwrap();
+    let path = s.resolve_path(path)?;
+    Ok(s.get_backlinks(&path).iter().map(|x| (*x).into()).collect())
+}
+
+#[tauri::command]
+fn get_node_by_id(id: &str) -> Option<NodeForJSInner> {
+    let s = STORAGE.get().unwrap().lock().unwrap();
+    s.get_node_by_id(id).map(|x| x.into())
+}
+
+/// turn an id ('id:...') or human path into the canonical human path
+#[tauri::command]
+fn resolve_node_path(path: &str) -> TauriResult<String> {
+    let s = STORAGE.get().unwrap().lock().unwrap();
+    Ok(s.resolve_path(path)?.to_human())
+}
+
 #[tauri::command]
 fn get_node_title(path: &str) -> Option<String> {
     let s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = s.resolve_path(path).ok()?;
     s.get_node(&path).map(|x| x.header.title.clone())
 }
 
@@ -196,24 +259,28 @@ fn descend(
 #[tauri::command]
 fn get_tree(path: &str, max_depth: i32) -> Option<TreeForJS> {
     let s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = s.resolve_path(path).ok()?;
     descend(&path, &s, max_depth)
 }
 
 #[tauri::command]
 fn get_parent(path: &str) -> TauriResult<String> {
-    let tp = TreePath::from_human(path)?;
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    let tp = ss.resolve_path(path)?;
     Ok(tp.parent().to_human())
 }
 
 #[tauri::command]
 fn move_node(org_path: &str, new_path: &str) -> Option<String> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    match s.move_node(
-        &TreePath::from_human(org_path).ok()?,
-        &TreePath::from_human(new_path).ok()?,
-        true,
-    ) {
+    let paths = s
+        .resolve_path(org_path)
+        .and_then(|org_path| Ok((org_path, s.resolve_path(new_path)?)));
+    let (org_path, new_path) = match paths {
+        Ok(paths) => paths,
+        Err(e) => return Some(e.to_string()),
+    };
+    match s.move_node(&org_path, &new_path, true) {
         Ok(_) => None,
         Err(e) => {
             println!("{:?}", &e);
@@ -225,7 +292,7 @@ fn move_node(org_path: &str, new_path: &str) -> Option<String> {
 #[tauri::command]
 fn swap_node_with_previous(path: &str) -> Option<String> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = s.resolve_path(path).ok()?;
     match s.swap_node_with_previous(&path) {
         Ok(_) => None,
         Err(e) => {
@@ -237,7 +304,7 @@ fn swap_node_with_previous(path: &str) -> Option<String> {
 #[tauri::command]
 fn swap_node_with_next(path: &str) -> Option<String> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = s.resolve_path(path).ok()?;
     match s.swap_node_with_next(&path) {
         Ok(_) => None,
         Err(e) => {
@@ -250,24 +317,67 @@ fn swap_node_with_next(path: &str) -> Option<String> {
 #[tauri::command]
 fn change_node_text(path: &str, text: &str, commit: Option<bool>) -> TauriResult<()> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
-    let tree_path = TreePath::from_human(path)?;
+    let tree_path = ss.resolve_path(path)?;
     let node = Node::new(&tree_path, text);
 
     ss.replace_node(node, commit.unwrap_or(true))?;
     let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-    lock.app_handle.emit_all("node-changed", path).ok();
+    lock.app_handle
+        .emit_all("node-changed", tree_path.to_human())
+        .ok();
     TauriResult::Ok(())
 }
+/// set a node property - or remove it by passing no value
+#[tauri::command]
+fn set_node_property(path: &str, key: &str, value: Option<&str>) -> TauriResult<()> {
+    let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    let tree_path = ss.resolve_path(path)?;
+    ss.set_node_property(&tree_path, key, value)?;
+    let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    lock.app_handle
+        .emit_all("node-changed", tree_path.to_human())
+        .ok();
+    TauriResult::Ok(())
+}
+
 #[tauri::command]
 fn commit(text: &str) -> TauriResult<()> {
     let ss = STORAGE.get().unwrap().lock().unwrap();
-    TauriResult::Ok(ss.add_and_commit(text)?)
+    ss.add_and_commit(text)?;
+    TauriResult::Ok(ss.flush_commits()?)
+}
+
+/// commit all queued changes now
+#[tauri::command]
+fn flush_commits() -> TauriResult<()> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    TauriResult::Ok(ss.flush_commits()?)
+}
+
+/// what went wrong while starting up (invalid settings...) - only once,
+/// so just one window shows them
+#[tauri::command]
+fn take_startup_messages() -> Vec<String> {
+    let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    std::mem::take(&mut lock.startup_messages)
+}
+
+/// every settings key with type, default and description
+#[tauri::command]
+fn get_settings_schema() -> Vec<settings::SettingInfo> {
+    settings::schema()
+}
+
+/// pull from and push to the remote configured in [sync]
+#[tauri::command]
+fn sync_now() -> TauriResult<sync::SyncStatus> {
+    TauriResult::Ok(sync::sync_and_report()?)
 }
 
 #[tauri::command]
 fn get_node_folder_path(path: &str) -> TauriResult<String> {
     let ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = ss.resolve_path(path)?;
     Ok(Node::dirname_from_path(&ss.data_path, &path)
         .to_string_lossy()
         .to_string())
@@ -276,15 +386,39 @@ fn get_node_folder_path(path: &str) -> TauriResult<String> {
 #[tauri::command]
 fn delete_node(path: &str, commit: Option<bool>) -> TauriResult<()> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = s.resolve_path(path)?;
     s.delete_node(&path, commit.unwrap_or(true))?;
     TauriResult::Ok(())
 }
 
+#[tauri::command]
+fn list_trash() -> TauriResult<Vec<storage::TrashEntry>> {
+    let s = STORAGE.get().unwrap().lock().unwrap();
+    Ok(s.list_trash()?)
+}
+
+/// restore a deleted subtree - to its original path unless target is given
+#[tauri::command]
+fn restore_from_trash(entry: &str, target: Option<&str>) -> TauriResult<String> {
+    let mut s = STORAGE.get().unwrap().lock().unwrap();
+    let target = target.map(|t| s.resolve_path(t)).transpose()?;
+    let restored = s.restore_from_trash(entry, target.as_ref())?;
+    Ok(restored.to_human())
+}
+
+/// permanently delete trash entries older than older_than_days (all if not given)
+#[tauri::command]
+fn empty_trash(older_than_days: Option<u32>) -> TauriResult<usize> {
+    let mut s = STORAGE.get().unwrap().lock().unwrap();
+    let cutoff = older_than_days
+        .map(|days| chrono::Local::now().naive_local() - chrono::Duration::days(days as i64));
+    Ok(s.empty_trash(cutoff)?)
+}
+
 #[tauri::command]
 fn sort_children(path: &str) -> TauriResult<()> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = s.resolve_path(path)?;
     s.sort_children(&path)?;
     TauriResult::Ok(())
 }
@@ -292,7 +426,7 @@ fn sort_children(path: &str) -> TauriResult<()> {
 #[tauri::command]
 fn compact_children(path: &str) -> TauriResult<()> {
     let mut s = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = s.resolve_path(path)?;
     s.compact_children(&path)?;
     TauriResult::Ok(())
 }
@@ -300,27 +434,17 @@ fn compact_children(path: &str) -> TauriResult<()> {
 #[tauri::command]
 fn edit_node(path: &str, window_title: &str, new_text: Option<&str>) -> TauriResult<bool> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = ss.resolve_path(path)?;
     dbg!("edit_node", &path);
     let mut runtime_state = RUNTIME_STATE.get().unwrap().lock().unwrap();
     if runtime_state
         .open_editors
         .iter()
-        .filter(|entry| entry.path == path.to_string())
+        .filter(|entry| entry.path == path.to_human())
         .next()
         .is_none()
     {
         {
-            let node_folder = Node::dirname_from_path(&ss.data_path, &path);
-            let tf_folder = node_folder;
-            std::fs::create_dir_all(&tf_folder).unwrap();
-            let tf = tf_folder.join(format!(
-                "{}.temp{}",
-                path.to_human().replace("/", "_"),
-                storage::FLORG_SUFFIX
-            ));
-            dbg!(&tf);
-
             let mut content = "".to_string();
             let mut skip_lines = 0;
             if path.is_empty() {
@@ -330,12 +454,7 @@ fn edit_node(path: &str, window_title: &str, new_text: Option<&str>) -> TauriRes
                 let mut so_far = TreePath::new();
 
                 for (ii, path_component) in path.iter().enumerate() {
-                    //todo: humanize
-                    let letter = if *path_component <= 26 {
-                        (('A' as u8 + *path_component as u8) as char).to_string()
-                    } else {
-                        path_component.to_string()
-                    };
+                    let letter = TreePath::component_to_human(*path_component);
                     content += &format!(
                         "{} {}{}\n",
                         letter,
@@ -358,22 +477,43 @@ fn edit_node(path: &str, window_title: &str, new_text: Option<&str>) -> TauriRes
                     content += node.as_ref().map(|x| &x.raw[..]).unwrap_or("");
                 }
             };
-            std::fs::write(&tf, &content).expect("temp file write failure");
-
             if let None = node {
                 let place_holder = Node::new(&path, "(placeholder)");
                 ss.replace_node(place_holder, false)?;
             }
-            edit_file(
+            let base = storage::EditBase {
+                path: path.clone(),
+                id: ss.get_node(&path).map(|x| x.id.clone()).unwrap_or_default(),
+                raw: node.map(|x| x.raw),
+            };
+            //a crash may have left a file with this session id
+            let (session, tf) = loop {
+                let session = NEXT_EDITOR_SESSION.fetch_add(1, Ordering::SeqCst);
+                let tf = ss.edit_temp_file(session, &path, &base.id);
+                if !tf.exists() {
+                    break (session, tf);
+                }
+            };
+            dbg!(&tf);
+            std::fs::create_dir_all(tf.parent().unwrap()).unwrap();
+            std::fs::write(&tf, &content).expect("temp file write failure");
+            let started = edit_file(
+                session,
                 tf,
                 content,
                 skip_lines,
-                "node-temp-changed",
+                Some("node-temp-changed"),
                 path.to_human(),
                 window_title,
+                &ss.settings.editor,
                 &mut runtime_state,
                 true,
+                Some(base),
             );
+            if let Err(e) = started {
+                ss.remove_placeholder(&path);
+                return Err(e.into());
+            }
         }
 
         TauriResult::Ok(true)
@@ -382,87 +522,35 @@ fn edit_node(path: &str, window_title: &str, new_text: Option<&str>) -> TauriRes
     }
 }
 fn edit_file(
+    session: u64,
     path: PathBuf,
     content: String,
     skip_lines: usize,
-    msg_to_js: &'static str,
+    msg_to_js: Option<&'static str>,
     path_for_js: String,
     window_title: &str,
+    editor_settings: &settings::EditorSettings,
     runtime_state: &mut MutexGuard<RuntimeState>,
     remove_after: bool,
-) {
+    base: Option<storage::EditBase>,
+) -> Result<()> {
     println!("path_for_js: {}", &path_for_js);
-    let process = std::process::Command::new("kitty")
-        .arg("--")
-        .arg("nvim")
-        .arg(format!("+{}", skip_lines))
-        .arg(path.to_string_lossy().to_string())
-        .spawn()
-        .expect("Failed to spawn kitty&neovim");
-    let tf_name_for_thread = path.file_name().map(|x| x.to_os_string()).unwrap();
-    let tf_for_thread = path.clone();
-    let path_for_thread = path_for_js.to_string();
-    thread::spawn(move || {
-        let mut inotify = Inotify::init().expect("inotify init failed");
-        let tf_folder = tf_for_thread.parent().unwrap();
-        inotify
-            .add_watch(
-                tf_folder,
-                WatchMask::CLOSE_WRITE | WatchMask::DELETE | WatchMask::MOVED_TO,
-            )
-            .expect("inotify add watch failed");
-        loop {
-            let mut buffer = [0; 1024];
-            let events = inotify
-                .read_events_blocking(&mut buffer)
-                .expect("Error while reading events");
-
-            println!("eventS!");
-
-            let mut do_send = false;
-            for event in events {
-                dbg!(&event);
-                /* if event.mask.contains(EventMask::DELETE) {
-                if let Some(event_filename) = event.name {
-                    if event_filename == tf_name_for_thread {
-                        break;
-                    };
-                } */
-                //if event.mask.contains(EventMask::CLOSE_WRITE) {
-                if let Some(event_filename) = event.name {
-                    if event_filename == tf_name_for_thread {
-                        do_send = true;
-                    }
-                }
-            }
-            if do_send {
-                let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-                let content = std::fs::read_to_string(&tf_for_thread);
-                if let Ok(content) = content {
-                    //we now also get an event when the temp file get's finally removed.
-                    let content = parse_raw_content(&content);
-                    //println!("Telling viewer about changed temp file");
-                    lock.app_handle
-                        .emit_all(&msg_to_js, (&path_for_thread, content))
-                        .ok();
-                }
-            }
-            //}
-            //dbg!(event);
-            //http://localhost:1420/#/node/T
-            //http://localhost:1420/#T
-            //dbg!(&tf_name_for_thread);
-            // Handle event
-        }
-    });
+    let process = editor::launch_editor(editor_settings, &path, skip_lines)?;
+    let wait = editor_settings.wait;
+    editor_watcher::watch(&path, msg_to_js, &path_for_js, wait)?;
     runtime_state.open_editors.push(OpenEditor {
+        id: session,
         path: path_for_js.to_string(),
         temp_file: path,
         org_content: content,
         process,
         window_title: window_title.to_string(),
         remove_after: remove_after,
+        wait,
+        saved: false,
+        base,
     });
+    Ok(())
 }
 
 fn get_settings_temp_filename(ss: &MutexGuard<Storage>) -> PathBuf {
@@ -472,29 +560,31 @@ fn get_settings_temp_filename(ss: &MutexGuard<Storage>) -> PathBuf {
     tf
 }
 
-fn spawn_settings_editor(tf: PathBuf, content: String, lock: &mut MutexGuard<RuntimeState>) {
-    //todo: combine with edit_file
-    let tf_name = tf.to_str().expect("temp file was not unicode name?");
-    let process = std::process::Command::new("kitty")
-        .arg("--")
-        .arg("nvim")
-        .arg(format!("+{}", 0))
-        .arg(tf_name)
-        .spawn()
-        .expect("Failed to spawn kitty&neovim");
-    lock.open_editors.push(OpenEditor {
-        path: "settings.toml".to_string(),
-        temp_file: tf,
-        org_content: content,
-        process,
-        window_title: "Settings".to_string(),
-        remove_after: true,
-    });
+fn spawn_settings_editor(
+    tf: PathBuf,
+    content: String,
+    editor_settings: &settings::EditorSettings,
+    lock: &mut MutexGuard<RuntimeState>,
+) -> Result<()> {
+    edit_file(
+        NEXT_EDITOR_SESSION.fetch_add(1, Ordering::SeqCst),
+        tf,
+        content,
+        0,
+        None,
+        "settings.toml".to_string(),
+        "Settings",
+        editor_settings,
+        lock,
+        true,
+        None,
+    )
 }
 
 #[tauri::command]
-fn edit_settings() -> bool {
+fn edit_settings() -> TauriResult<bool> {
     let path = "settings.toml";
+    let ss = STORAGE.get().unwrap().lock().unwrap();
     let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
 
     if lock
@@ -504,20 +594,16 @@ fn edit_settings() -> bool {
         .next()
         .is_none()
     {
-        {
-            let ss = STORAGE.get().unwrap().lock().unwrap();
-            let tf = get_settings_temp_filename(&ss);
-            dbg!(&ss.settings);
-            let content = ss.settings.to_string();
+        let tf = get_settings_temp_filename(&ss);
+        let content = ss.settings_raw();
 
-            std::fs::write(&tf, &content).expect("temp file write failure");
+        std::fs::write(&tf, &content).expect("temp file write failure");
 
-            spawn_settings_editor(tf, content, &mut lock);
-        }
+        spawn_settings_editor(tf, content, &ss.settings.editor, &mut lock)?;
 
-        true
+        Ok(true)
     } else {
-        false
+        Ok(false)
     }
 }
 
@@ -579,7 +665,7 @@ fn chrono_date_to_path(date: chrono::NaiveDate) -> TreePath {
 #[tauri::command]
 fn create_calendar(parent_path: &str, year: i32) -> TauriResult<()> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
-    let parent_path = TreePath::from_human(parent_path)?;
+    let parent_path = ss.resolve_path(parent_path)?;
     if !ss.children_for(&parent_path).is_empty() {
         return TauriResult::<()>::Err(TauriError(anyhow!(
             "Node had children - not filling in calendar nodes"
@@ -622,7 +708,10 @@ fn create_calendar(parent_path: &str, year: i32) -> TauriResult<()> {
         ss.replace_node(node, false)?;
     }
     println!("added days");
-    ss.add_and_commit(&format!("Added date notes below {parent_path}"))?;
+    ss.add_and_commit(&format!(
+        "Added date notes below {}",
+        parent_path.to_human()
+    ))?;
     TauriResult::Ok(())
 }
 #[tauri::command]
@@ -630,16 +719,61 @@ fn reload_data() {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
     ss.reload();
     println!("reloaded storage");
+    if let Some(e) = ss.settings_error() {
+        let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+        lock.app_handle
+            .emit_all("message", settings_error_message(e))
+            .ok();
+    }
+    if !ss.duplicate_aliases().is_empty() {
+        let dups: Vec<String> = ss
+            .duplicate_aliases()
+            .iter()
+            .map(|dup| format!("@{} ({})", dup.alias, dup.paths.join(", ")))
+            .collect();
+        let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+        lock.app_handle
+            .emit_all(
+                "message",
+                format!(
+                    "<span class='error'>Aliases used by more than one node: {}</span>",
+                    dups.join("; ")
+                ),
+            )
+            .ok();
+    }
+}
+
+fn settings_error_message(error: &str) -> String {
+    format!(
+        "<span class='error'>Invalid settings, using the defaults for the broken parts: {}</span>",
+        error
+    )
+}
+
+/// alias -> human path
+#[tauri::command]
+fn list_aliases() -> BTreeMap<String, String> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.aliases()
+}
+
+#[tauri::command]
+fn list_duplicate_aliases() -> Vec<storage::DuplicateAlias> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.duplicate_aliases()
 }
 
 #[tauri::command]
 fn get_tags() -> Option<HashMap<String, String>> {
-    get_from_settings_str_map("tags")
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.settings.tags.clone()
 }
 
 #[tauri::command]
 fn get_bookmarks() -> Option<HashMap<String, String>> {
-    get_from_settings_str_map("bookmarks")
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.settings.bookmarks.clone()
 }
 #[tauri::command]
 fn set_bookmarks(bookmarks: HashMap<String, String>) -> TauriResult<()> {
@@ -649,18 +783,20 @@ fn set_bookmarks(bookmarks: HashMap<String, String>) -> TauriResult<()> {
 
 #[tauri::command]
 fn get_nav() -> Option<HashMap<String, String>> {
-    get_from_settings_str_map("nav")
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.settings.nav.clone()
 }
 
 #[tauri::command]
 fn get_mail_search_folders() -> Option<HashMap<String, String>> {
-    get_from_settings_str_map("mail_queries")
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.settings.mail_queries.clone()
 }
 
 #[tauri::command]
 fn find_next_empty_child(path: &str) -> Result<String, TauriError> {
     let ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = ss.resolve_path(path)?;
     Ok(ss.find_next_empty_child(&path).to_human())
 }
 
@@ -673,6 +809,44 @@ struct RipgrepResult {
     tags: Vec<String>,
 }
 
+impl RipgrepResult {
+    fn new(ss: &Storage, path: &TreePath, lines: Vec<(u32, String)>) -> RipgrepResult {
+        let node = ss.get_node(&path);
+        let title = node
+            .map(|x| x.header.title.clone())
+            .unwrap_or_else(|| "(empty node)".to_string());
+        let mut parent_titles = Vec::new();
+        let mut cpath = path.clone();
+        let tags = node.map(|x| x.get_tags()).unwrap_or_default();
+        while !cpath.is_empty() {
+            cpath.pop();
+            parent_titles.push(
+                ss.get_node(&cpath)
+                    .map(|x| x.header.title.clone())
+                    .unwrap_or_else(|| "(empty node)".to_string()),
+            );
+        }
+        RipgrepResult {
+            path: path.to_human(),
+            title,
+            parent_titles,
+            lines,
+            tags,
+        }
+    }
+}
+
+/// structured search, e.g. 'tag:#project AND below:B AND NOT tag:#done AND modified>2026-01-01'
+#[tauri::command]
+fn query_nodes(query: &str) -> TauriResult<Vec<RipgrepResult>> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    Ok(ss
+        .query_nodes(query)?
+        .into_iter()
+        .map(|(node, lines)| RipgrepResult::new(&ss, &node.path, lines))
+        .collect())
+}
+
 #[tauri::command]
 fn ripgrep_below_node(
     query_path: &str,
@@ -680,99 +854,19 @@ fn ripgrep_below_node(
     only_matching: Option<bool>,
 ) -> Option<Vec<RipgrepResult>> {
     let ss = STORAGE.get().unwrap().lock().unwrap();
-    let query_path = TreePath::from_human(query_path).ok()?;
-    let search_path = Node::dirname_from_path(&ss.data_path, &query_path);
-    println!(
-        "searching in {search_path:?}, only_matching: {:?}",
-        only_matching
-    );
-    let mut cmd = std::process::Command::new("rg");
-    cmd.arg("--type-add")
-        .arg("adoc:*.adoc")
-        .arg("-t")
-        .arg("adoc")
-        .arg("-i")
-        .arg("--line-number")
-        .arg("--heading")
-        .arg(search_term);
-    if let Some(true) = only_matching {
-        cmd.arg("--only-matching");
-    }
-
-    let ok = cmd.current_dir(search_path).output();
-    match ok {
-        Ok(output) => {
-            let stdout = std::str::from_utf8(&output.stdout).unwrap();
-            dbg!(stdout);
-            let mut result = Vec::new();
-            for block in stdout.split("\n\n") {
-                if block.trim().is_empty() {
-                    continue;
-                }
-                let mut lines = block.split("\n");
-                let filename = lines.next();
-                let path = match filename {
-                    Some(filename) => {
-                        if filename.contains(".temp.") {
-                            continue;
-                        }
-                        let filename = filename.trim_end_matches(storage::FLORG_FILENAME);
-                        match TreePath::from_file_path(filename) {
-                            Ok(path) => path,
-                            Err(e) => {
-                                println!("failed to parse file path: {} {}", filename, e);
-                                continue;
-                            }
-                        }
-                    }
-                    None => continue,
-                };
-                let path = query_path.concat(&path);
-                let node = ss.get_node(&path);
-                let title = node
-                    .map(|x| x.header.title.clone())
-                    .unwrap_or_else(|| "(empty node)".to_string());
-                let mut parent_titles = Vec::new();
-                let mut cpath = path.clone();
-                let tags = node.map(|x| x.get_tags()).unwrap_or_default();
-                while !cpath.is_empty() {
-                    cpath.pop();
-                    parent_titles.push(
-                        ss.get_node(&cpath)
-                            .map(|x| x.header.title.clone())
-                            .unwrap_or_else(|| "(empty node)".to_string()),
-                    );
-                }
-                let mut hits = Vec::new();
-                for further in lines {
-                    let (line_no, hit) = match further.split_once(":") {
-                        Some((line_no, hit)) => (line_no, hit),
-                        None => continue,
-                    };
-                    hits.push((line_no.parse::<u32>().unwrap_or(0), hit.to_string()));
-                }
-                result.push(RipgrepResult {
-                    path: path.to_human(),
-                    title,
-                    parent_titles,
-                    lines: hits,
-                    tags,
-                })
-            }
-            result.sort_by(|a, b| a.path.cmp(&b.path));
-            Some(result)
-        }
-        Err(e) => {
-            println!("error running ripgrep: {}", e);
-            None
-        }
-    }
+    let query_path = ss.resolve_path(query_path).ok()?;
+    Some(
+        ss.search_below(&query_path, search_term, only_matching.unwrap_or(false))
+            .into_iter()
+            .map(|(node, lines)| RipgrepResult::new(&ss, &node.path, lines))
+            .collect(),
+    )
 }
 
 #[tauri::command]
 fn find_first_below(path: &str, query: &str, title_only: Option<bool>) -> Option<String> {
     let ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = ss.resolve_path(path).ok()?;
     //now depth first search into the children of path
     let qs = query.to_string();
     let to = title_only.unwrap_or(false);
@@ -788,32 +882,67 @@ fn find_first_below(path: &str, query: &str, title_only: Option<bool>) -> Option
     )
     .map(|node| node.path.to_human())
 }
+#[derive(Serialize, Debug)]
+struct FuzzyResult {
+    path: String,
+    title: String,
+    levels: Vec<(String, String)>,
+    score: i64,
+}
+
+/// ranked fuzzy title matches below 'below' (default: everywhere) for goto/QuickPick
+#[tauri::command]
+fn fuzzy_find_nodes(
+    query: &str,
+    below: Option<&str>,
+    limit: Option<usize>,
+) -> TauriResult<Vec<FuzzyResult>> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    let below = match below {
+        Some(below) => ss.resolve_path(below)?,
+        None => TreePath::new(),
+    };
+    Ok(ss
+        .fuzzy_find_nodes(query, &below, limit.unwrap_or(50))
+        .into_iter()
+        .map(|(score, node)| FuzzyResult {
+            path: node.path.to_human(),
+            title: node.header.title.clone(),
+            levels: ss.levels(&node.path),
+            score,
+        })
+        .collect())
+}
+
 #[tauri::command]
 fn get_cached_node(path: &str) -> Option<String> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path).ok()?;
+    let path = ss.resolve_path(path).ok()?;
     ss.get_cached_node(&path)
 }
 
 #[tauri::command]
 fn set_cached_node(path: &str, raw: &str, rendered: &str) -> TauriResult<()> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
-    let path = TreePath::from_human(path)?;
+    let path = ss.resolve_path(path)?;
     Ok(ss.set_cached_node(&path, raw, rendered)?)
 }
 
 #[tauri::command]
 fn query_mail(query: &str) -> (Vec<mail::Thread>, bool) {
+    let filtered_authors: HashSet<String> = {
+        let ss = STORAGE.get().unwrap().lock().unwrap();
+        ss.settings.mail.filtered_authors.iter().cloned().collect()
+    };
     let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-    let mut filtered_authors = HashSet::new();
-    filtered_authors.insert("Florian Finkernagel".to_string()); //todo: read from settings
     let res = lock.notmuch_db.query(query, &filtered_authors);
     res
 }
 
 #[tauri::command]
 fn mail_get_tags() -> Option<HashMap<String, String>> {
-    get_from_settings_str_map("mail_tags")
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    ss.settings.mail_tags.clone()
 }
 
 #[tauri::command]
@@ -862,12 +991,7 @@ fn mail_message_store_attachments(id: &str) -> bool {
     fn inner(id: &str) -> anyhow::Result<()> {
         let ss = STORAGE.get().unwrap().lock().unwrap();
 
-        let attachment_dir = ss
-            .settings
-            .get("mail.attachment_dir")
-            .map(|x| x.to_string())
-            .unwrap_or("~/attachments".to_string());
-        let attachment_dir = expanduser::expanduser(attachment_dir)?;
+        let attachment_dir = expanduser::expanduser(&ss.settings.mail.attachment_dir)?;
         let attachment_dir = PathBuf::from(&attachment_dir);
         if attachment_dir.exists() {
             std::fs::remove_dir_all(&attachment_dir)?;
@@ -885,20 +1009,32 @@ fn mail_message_store_attachments(id: &str) -> bool {
     res.is_ok()
 }
 #[tauri::command]
-fn mail_message_new(id: Option<String>, window_title: &str) {
+fn mail_message_new(id: Option<String>, window_title: &str) -> TauriResult<()> {
+    let editor_settings = STORAGE
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .settings
+        .editor
+        .clone();
     let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
     let (filename, org_content) = lock.notmuch_db.new_mail(id, Vec::new());
     let path_for_js = filename.file_name().unwrap().to_string_lossy().to_string();
     edit_file(
+        NEXT_EDITOR_SESSION.fetch_add(1, Ordering::SeqCst),
         filename,
         org_content,
         0, //todo
-        "mail-temp-changed",
+        Some("mail-temp-changed"),
         path_for_js,
         window_title,
+        &editor_settings,
         &mut lock,
         false,
-    );
+        None,
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
@@ -995,11 +1131,25 @@ fn history_store(name: &str, entries: Vec<String>) -> bool {
 }
 #[tauri::command]
 fn start_terminal(folder: String) -> bool {
-    let process = std::process::Command::new("kitty")
-        .current_dir(folder)
-        .spawn();
-    //    .expect("Failed to spawn kitty");
-    process.is_ok()
+    let terminal_settings = STORAGE
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .settings
+        .terminal
+        .clone();
+    match editor::launch_terminal(&terminal_settings, &PathBuf::from(folder)) {
+        Ok(child) => {
+            let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+            lock.other_children.push(child);
+            true
+        }
+        Err(e) => {
+            println!("{:?}", e);
+            false
+        }
+    }
 }
 
 #[tauri::command]
@@ -1021,26 +1171,134 @@ fn get_git_history(limit: Option<u32>) -> Vec<storage::GitHistoryEntry> {
     }
 }
 
+#[tauri::command]
+fn get_node_history(path: &str, limit: Option<u32>) -> TauriResult<Vec<storage::NodeHistoryEntry>> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    let path = ss.resolve_path(path)?;
+    Ok(ss.get_node_history(&path, limit.unwrap_or(100))?)
+}
+
+#[tauri::command]
+fn get_node_at_revision(path: &str, hash: &str) -> TauriResult<String> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    let path = ss.resolve_path(path)?;
+    Ok(ss.get_node_at_revision(&path, hash)?)
+}
+
+/// restore just this node's text from an older revision
+#[tauri::command]
+fn restore_node(path: &str, hash: &str) -> TauriResult<()> {
+    let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    let tree_path = ss.resolve_path(path)?;
+    ss.restore_node(&tree_path, hash)?;
+    let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    lock.app_handle
+        .emit_all("node-changed", tree_path.to_human())
+        .ok();
+    TauriResult::Ok(())
+}
+
+/// editor temp files without a running editor - e.g. after a crash
+#[tauri::command]
+fn list_leftover_edits() -> Vec<storage::LeftoverEdit> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    let open: Vec<PathBuf> = RUNTIME_STATE
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .open_editors
+        .iter()
+        .map(|e| e.temp_file.clone())
+        .collect();
+    ss.find_leftover_edits(&open)
+}
+
+/// action: 'apply', 'discard' or 'new' (save as a sibling)
+#[tauri::command]
+fn resolve_leftover_edit(name: &str, action: &str) -> TauriResult<Option<String>> {
+    let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    let written = ss.resolve_leftover_edit(name, storage::LeftoverAction::parse(action)?)?;
+    if let Some(path) = &written {
+        let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+        lock.app_handle
+            .emit_all("node-changed", path.to_human())
+            .ok();
+    }
+    Ok(written.map(|p| p.to_human()))
+}
+
+/// (path, title) of all nodes with git conflict markers
+#[tauri::command]
+fn list_conflicts() -> TauriResult<Vec<(String, String)>> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    Ok(ss
+        .conflicted_nodes()
+        .iter()
+        .map(|n| (n.path.to_human(), n.header.title.clone()))
+        .collect())
+}
+
+/// choice_or_text: 'ours', 'theirs' or the hand merged node text
+#[tauri::command]
+fn resolve_conflict(path: &str, choice_or_text: &str) -> TauriResult<()> {
+    let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    let tree_path = ss.resolve_path(path)?;
+    ss.resolve_conflict(
+        &tree_path,
+        storage::ConflictResolution::from_choice(choice_or_text),
+    )?;
+    let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    lock.app_handle
+        .emit_all("node-changed", tree_path.to_human())
+        .ok();
+    TauriResult::Ok(())
+}
+
+/// which nodes a commit changed, and how - for the undo screen
+#[tauri::command]
+fn get_commit_diff(hash: &str) -> TauriResult<storage::CommitDiff> {
+    let ss = STORAGE.get().unwrap().lock().unwrap();
+    Ok(ss.get_commit_diff(hash)?)
+}
+
+//undo/redo rewrite node files - not while somebody is editing one.
+//Editors are opened under the STORAGE lock, so with its guard held
+//none can open between this check and the revert.
+fn check_no_open_editors(_storage: &MutexGuard<Storage>) -> Result<()> {
+    let rt = RUNTIME_STATE.get().unwrap().lock().unwrap();
+    if !rt.open_editors.is_empty() {
+        return Err(anyhow!(
+            "Close all editors first ({} open)",
+            rt.open_editors.len()
+        ));
+    }
+    Ok(())
+}
+
 #[tauri::command]
 fn git_undo(hash: &str) -> Option<String> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    if let Err(err) = check_no_open_editors(&ss) {
+        return Some(err.to_string());
+    }
     match ss.git_undo(hash) {
         Ok(_) => None,
         Err(err) => Some(err.to_string()),
     }
 }
 
-fn get_from_settings_str_map(key: &str) -> Option<HashMap<String, String>> {
-    let ss = STORAGE.get().unwrap().lock().unwrap();
-    Some(
-        ss.settings
-            .get(key)?
-            .as_table()?
-            .iter()
-            .map(|(k, v)| (Some((k.to_string(), v.as_str()?.to_string()))))
-            .filter_map(|x| x)
-            .collect(),
-    )
+/// revert an undo - the last commit, or the undo commit hash
+#[tauri::command]
+fn git_redo(hash: Option<&str>) -> Option<String> {
+    let mut ss = STORAGE.get().unwrap().lock().unwrap();
+    if let Err(err) = check_no_open_editors(&ss) {
+        return Some(err.to_string());
+    }
+    match ss.git_redo(hash) {
+        Ok(_) => None,
+        Err(err) => Some(err.to_string()),
+    }
 }
 
 fn save_to_settings_str_map(key: &str, map: HashMap<String, String>) -> Result<()> {
@@ -1049,20 +1307,9 @@ fn save_to_settings_str_map(key: &str, map: HashMap<String, String>) -> Result<(
     for (k, v) in map {
         table.insert(&k, toml_edit::Item::Value(v.into()));
     }
-    ss.settings.insert(key, toml_edit::Item::Table(table));
-    ss.store_settings()
-}
-
-fn find_git_binary() -> Result<String> {
-    Ok(std::str::from_utf8(
-        &std::process::Command::new("which")
-            .arg("git")
-            .output()
-            .context("find git binary with which failed")?
-            .stdout,
-    )?
-    .trim()
-    .to_string())
+    ss.update_settings(|doc| {
+        doc.insert(key, toml_edit::Item::Table(table));
+    })
 }
 
 fn init_data_path(data_path: &PathBuf) -> Result<()> {
@@ -1071,41 +1318,10 @@ fn init_data_path(data_path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn init_data_path_git(data_path: &PathBuf, git_binary: &str) -> Result<()> {
-    dbg!(git_binary);
-    //check if it has a .git, or any parent path has
-    let mut has_git = false;
-    let mut current_path = data_path.clone();
-    while current_path != PathBuf::from("/") {
-        if current_path.join(".git").exists() {
-            has_git = true;
-            break;
-        }
-        current_path = current_path.parent().unwrap().to_path_buf();
-    }
-    if !has_git {
-        std::process::Command::new(git_binary)
-            .arg("init")
-            .arg(".")
-            .current_dir(data_path)
-            .status()
-            .context("Git init failed")?;
-    }
-    std::process::Command::new(git_binary)
-        .arg("add")
-        .arg(".")
-        .arg("--all")
-        .current_dir(data_path)
-        .status()
-        .context("git add . --all failed")?;
-    std::process::Command::new(git_binary)
-        .arg("commit")
-        .arg("-m")
-        .arg("Capturing status quo on new florg data path")
-        .current_dir(data_path)
-        .status()
-        .context("git initial commit failed")?;
-
+fn init_data_path_git(data_path: &PathBuf) -> Result<()> {
+    //uses the repository of a parent directory if there is one
+    let repo = storage::Repository::open_or_init(data_path)?;
+    repo.add_all_and_commit("Capturing status quo on new florg data path")?;
     Ok(())
 }
 
@@ -1114,116 +1330,283 @@ fn init_data_path_gitignore(data_path: &PathBuf) -> Result<()> {
         data_path.join(".gitignore"),
         "*.temp.adoc
 *.cache
+.trash/
 ",
     )?)
 }
 
-fn editor_ended() {
-    let mut remove: Vec<(usize, String, Option<String>)> = Vec::new();
-    {
-        let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-        for (ii, entry) in lock.open_editors.iter_mut().enumerate() {
+/// The text of a finished editor session - None if there was no (usable) change
+fn finished_edit_result(entry: &OpenEditor, exit_success: bool) -> Option<String> {
+    if !exit_success {
+        return None;
+    }
+    match std::fs::read_to_string(&entry.temp_file) {
+        Ok(raw) if raw.is_empty() || raw == entry.org_content => None,
+        Ok(raw) => Some(raw),
+        Err(e) => {
+            println!("Failed to read temp file {:?}: {}", entry.temp_file, e);
+            None
+        }
+    }
+}
+
+/// How an editor session ended
+enum EditorEnd {
+    //the edited text - None if there was no (usable) change
+    Finished(Option<String>),
+    //we can't tell what became of the editor - its temp file is kept as a leftover edit
+    Lost,
+}
+
+/// Take the finished sessions out of open_editors - under the RUNTIME_STATE lock,
+/// so concurrent callers (SIGCHLD, the supervisor, the temp file watcher)
+/// never handle a session twice.
+fn take_finished_editors(open_editors: &mut Vec<OpenEditor>) -> Vec<(OpenEditor, EditorEnd)> {
+    let mut finished: Vec<(u64, EditorEnd)> = Vec::new();
+    for entry in open_editors.iter_mut() {
+        let result = if entry.wait == settings::WaitMode::Save {
+            //the (forking) editor process tells us nothing, but reap it
+            entry.process.try_wait().ok();
+            if entry.saved {
+                Some(EditorEnd::Finished(finished_edit_result(entry, true)))
+            } else {
+                None
+            }
+        } else {
             match entry.process.try_wait() {
-                Ok(res) => match res {
-                    Some(exit_status) => {
-                        if exit_status.success() {
-                            let raw = std::fs::read_to_string(&entry.temp_file)
-                                .expect("Failed to read tempfile");
-                            //dbg!(raw == entry.org_content);
-                            //dbg!(&raw);
-                            //dbg!(&entry.org_content);
-                            if raw.is_empty() || (raw == entry.org_content) {
-                                remove.push((ii, entry.path.to_string(), None))
-                            } else {
-                                remove.push((ii, entry.path.to_string(), Some(raw)));
-                            }
-                        } else {
-                            remove.push((ii, entry.path.to_string(), None))
-                        }
-                    }
-                    None => {} // still processing
-                },
-                Err(_) => panic!("error return from child process?!"),
+                Ok(Some(exit_status)) => Some(EditorEnd::Finished(finished_edit_result(
+                    entry,
+                    exit_status.success(),
+                ))),
+                Ok(None) => None, // still running
+                Err(_) => Some(EditorEnd::Lost),
             }
+        };
+        if let Some(result) = result {
+            finished.push((entry.id, result));
         }
     }
-    for (ii, path, result) in remove {
-        let window_title = {
+    let mut results: HashMap<u64, EditorEnd> = finished.into_iter().collect();
+    let (done, running): (Vec<OpenEditor>, Vec<OpenEditor>) = std::mem::take(open_editors)
+        .into_iter()
+        .partition(|entry| results.contains_key(&entry.id));
+    *open_editors = running;
+    done.into_iter()
+        .map(|entry| {
+            let result = results.remove(&entry.id).unwrap();
+            (entry, result)
+        })
+        .collect()
+}
+
+fn editor_ended() {
+    let finished = {
+        let mut lock = match RUNTIME_STATE.get() {
+            Some(rt) => rt.lock().unwrap(),
+            None => return, // SIGCHLD before the app is up
+        };
+        lock.other_children
+            .retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_)) | Err(_)));
+        take_finished_editors(&mut lock.open_editors)
+    };
+    for (entry, end) in finished {
+        //a forking editor (WaitMode::Save) may still run after the save,
+        //one we lost track of as well - reap them with the terminals
+        let mut process = entry.process;
+        if !matches!(process.try_wait(), Ok(Some(_))) {
             let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-            if lock.open_editors[ii].remove_after {
-                std::fs::remove_file(&lock.open_editors[ii].temp_file).ok();
+            lock.other_children.push(process);
+        }
+        editor_watcher::unwatch(&entry.temp_file);
+        let path = entry.path.clone();
+        let result = match end {
+            EditorEnd::Finished(result) => result,
+            EditorEnd::Lost => {
+                //the editor may still be writing - keep the file (and the placeholder)
+                let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+                lock.app_handle
+                    .emit_all(
+                        "message",
+                        format!(
+                            "<span class='error'>Lost track of the editor for {}. \
+                             The edit is kept in {}</span>",
+                            path,
+                            entry.temp_file.display()
+                        ),
+                    )
+                    .ok();
+                continue;
             }
-            let window_title = lock.open_editors[ii].window_title.clone();
-            lock.open_editors.remove(ii);
-            window_title
         };
-
+        if entry.remove_after {
+            std::fs::remove_file(&entry.temp_file).ok();
+        }
         match result {
+            //mail drafts are edited in place, nothing to store
+            Some(_) if entry.base.is_none() && path != "settings.toml" => {}
             Some(raw) => {
-                println!("Received {path}. nvim exit was success, content was there");
-                update_from_edited_file(&path, raw, &window_title).unwrap();
-            }
-            None => {
-                if path != "settings.toml" {
+                println!("Received {path}. editor exit was success, content was there");
+                if let Err(e) = update_from_edited_file(&path, raw, &entry.window_title, entry.base)
+                {
+                    println!("failed to store edit of {}: {:?}", path, e.0);
                     let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
-                    let mut ss = STORAGE.get().unwrap().lock().unwrap();
-                    ss.remove_placeholder(
-                        &TreePath::from_human(&path)
-                            .expect("Failed to parse path from open editors"),
-                    ); //todo
-                    lock.app_handle.emit_all("node-unchanged", &path).ok();
+                    lock.app_handle
+                        .emit_all(
+                            "message",
+                            format!(
+                                "<span class='error'>Failed to store {}: {}</span>",
+                                path, e.0
+                            ),
+                        )
+                        .ok();
                 }
             }
+            None => discard_edit(&entry.path, entry.base.as_ref()),
+        }
+    }
+}
+
+/// an edit ended without changes - drop the placeholder of a new node
+fn discard_edit(path_for_js: &str, base: Option<&storage::EditBase>) {
+    if let Some(base) = base {
+        let mut ss = STORAGE.get().unwrap().lock().unwrap();
+        let path = ss
+            .get_node_by_id(&base.id)
+            .map(|n| n.path.clone())
+            .unwrap_or_else(|| base.path.clone());
+        ss.remove_placeholder(&path);
+        drop(ss);
+        let lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+        lock.app_handle.emit_all("node-unchanged", path_for_js).ok();
+    }
+}
+
+/// Reap editors (and terminals) even if a SIGCHLD got lost or coalesced
+fn spawn_editor_supervisor() {
+    thread::spawn(|| loop {
+        thread::sleep(std::time::Duration::from_secs(1));
+        if RUNTIME_STATE.get().is_some() {
+            editor_ended();
+        }
+    });
+}
+
+/// Kill the editor for path and throw away its temp file.
+/// False if there was no editor open for path.
+#[tauri::command]
+fn cancel_edit(path: &str) -> TauriResult<bool> {
+    //node editors are known by human path - settings.toml and mail drafts by name
+    let path = match STORAGE.get().unwrap().lock().unwrap().resolve_path(path) {
+        Ok(path) => path.to_human(),
+        Err(_) => path.to_string(),
+    };
+    let mut entry = {
+        let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
+        match lock.open_editors.iter().position(|e| e.path == path) {
+            Some(pos) => lock.open_editors.remove(pos),
+            None => return Ok(false),
         }
+    };
+    editor_watcher::unwatch(&entry.temp_file);
+    //fails if it exited in the meantime - wait() reaps it either way
+    entry.process.kill().ok();
+    entry.process.wait().ok();
+    //mail drafts are not temp files - keep them
+    if entry.remove_after {
+        std::fs::remove_file(&entry.temp_file).ok();
     }
+    discard_edit(&entry.path, entry.base.as_ref());
+    Ok(true)
 }
 
 fn update_from_edited_file(
     path: &str,
     raw_contents: String,
     window_title: &str,
+    base: Option<storage::EditBase>,
 ) -> TauriResult<()> {
     let mut ss = STORAGE.get().unwrap().lock().unwrap();
     let mut lock = RUNTIME_STATE.get().unwrap().lock().unwrap();
 
     if path == "settings.toml" {
         println!("received settings");
-        let settings = Storage::load_settings(&ss.data_path, Some(raw_contents.clone()));
-        dbg!("Received settings", &settings);
-        match settings {
-            Ok(new_settings) => {
-                ss.settings = new_settings;
+        match ss.set_settings_raw(&raw_contents) {
+            Ok(()) => {
                 println!("Updated settings {:?}", &ss.settings);
-                ss.store_settings()?;
                 lock.app_handle.emit_all("message", "Settings updated").ok();
             }
-            Err(_) => {
+            Err(e) => {
                 lock.app_handle
                     .emit_all(
                         "message",
-                        "<span class='error'>Settings could not be parsed. Try again</span>",
+                        format!(
+                            "<span class='error'>Settings could not be parsed: {}. Try again</span>",
+                            e
+                        ),
                     )
                     .ok();
                 let tf = get_settings_temp_filename(&ss);
                 println!("Writing contents - len {}", raw_contents.len());
                 std::fs::write(&tf, &raw_contents).expect("failed to write settings temp file");
-                spawn_settings_editor(tf, raw_contents, &mut lock);
+                let editor_settings = ss.settings.editor.clone();
+                spawn_settings_editor(tf, raw_contents, &editor_settings, &mut lock)?;
             }
         }
     } else {
         let content = parse_raw_content(&raw_contents);
         println!("parsed contents {}", path);
 
-        let node = storage::Node::new(
-            &TreePath::from_human(path).expect("Failed to parse treepath from open editor"),
-            content,
-        );
-        ss.replace_node(node, true)?;
-        println!("Replaced node");
+        let base = match base {
+            Some(base) => base,
+            None => {
+                let path = TreePath::from_human(path)?;
+                storage::EditBase {
+                    id: ss.get_node(&path).map(|x| x.id.clone()).unwrap_or_default(),
+                    raw: ss.get_node(&path).map(|x| x.raw.clone()),
+                    path,
+                }
+            }
+        };
+        let outcome = ss.save_edited_node(&base, content)?;
+        println!("Saved node: {:?}", outcome);
 
-        lock.app_handle
-            .emit_to(window_title, "node-changed", path)
-            .ok();
+        match outcome {
+            storage::EditOutcome::Saved(path) => {
+                lock.app_handle
+                    .emit_to(window_title, "node-changed", path.to_human())
+                    .ok();
+            }
+            storage::EditOutcome::Merged(path) => {
+                lock.app_handle
+                    .emit_to(window_title, "node-changed", path.to_human())
+                    .ok();
+                lock.app_handle
+                    .emit_all(
+                        "message",
+                        format!(
+                            "Node {} was changed while you edited it - merged both changes",
+                            path.to_human()
+                        ),
+                    )
+                    .ok();
+            }
+            storage::EditOutcome::SavedAsSibling { original, sibling } => {
+                lock.app_handle
+                    .emit_all("node-changed", sibling.to_human())
+                    .ok();
+                lock.app_handle
+                    .emit_all(
+                        "message",
+                        format!(
+                            "<span class='error'>Node {} was changed while you edited it. \
+                             Your version was saved as {}</span>",
+                            original.to_human(),
+                            sibling.to_human()
+                        ),
+                    )
+                    .ok();
+            }
+        }
         println!("Told editor");
     }
     TauriResult::Ok(())
@@ -1243,8 +1626,6 @@ fn main() -> Result<()> {
                     .expect("Failed to create state directory in xdg state path")
             })
     };
-    let git_binary = find_git_binary()?;
-
     if data_path
         .read_dir()
         .expect("could not find data_path")
@@ -1254,14 +1635,37 @@ fn main() -> Result<()> {
         init_data_path(&data_path).expect("Could not write initial contents");
     }
     if !(data_path.join(".git")).exists() {
-        init_data_path_git(&data_path, &git_binary).expect("failed to init git dir");
+        init_data_path_git(&data_path).expect("failed to init git dir");
     }
     if !(data_path.join(".gitignore")).exists() {
         init_data_path_gitignore(&data_path).expect("failed to init gitignore");
     }
 
-    let s = Mutex::new(Storage::new(data_path, git_binary));
-    STORAGE.set(s).unwrap();
+    let mut s = Storage::new(data_path.clone())?;
+    //nothing can be open in an editor yet - whatever is left is from a crash
+    let leftovers = s.recover_edits();
+    let mut startup_messages = Vec::new();
+    if !leftovers.is_empty() {
+        println!(
+            "{} unsaved edits from the last run, see list_leftover_edits",
+            leftovers.len()
+        );
+        let paths: Vec<&str> = leftovers.iter().map(|l| &l.path[..]).collect();
+        startup_messages.push(format!(
+            "<span class='error'>{} unsaved edits from the last run (nodes {}) - \
+             kept until they are applied or discarded</span>",
+            leftovers.len(),
+            paths.join(", ")
+        ));
+    }
+    if let Some(e) = s.settings_error() {
+        startup_messages.push(settings_error_message(e));
+    }
+    STORAGE.set(Mutex::new(s)).unwrap();
+    commit_queue::spawn_background_commits();
+    if let Err(e) = watcher::spawn_data_watcher(data_path) {
+        println!("could not watch data path, changes outside of florg need a reload: {e:?}");
+    }
 
     let mut signals = Signals::new(&[signal_hook::consts::SIGCHLD])?;
     let signal_handle = signals.handle();
@@ -1275,18 +1679,8 @@ fn main() -> Result<()> {
         }
     });
 
-    fn get_mail_setting(key: &str) -> Option<String> {
-        let ss = STORAGE.get().unwrap().lock().unwrap();
-        Some(
-            ss.settings
-                .get("mail")?
-                .as_table()?
-                .get(key)?
-                .as_str()?
-                .to_string(),
-        )
-    }
-    let mail_path = get_mail_setting("mail_dir").unwrap_or_else(|| {
+    let mail_settings = STORAGE.get().unwrap().lock().unwrap().settings.mail.clone();
+    let mail_path = mail_settings.mail_dir.unwrap_or_else(|| {
         dirs::home_dir()
             .expect(
                 "Could not find home dir, can't guess maildir, set mail.mail_dir in settings.toml",
@@ -1295,7 +1689,7 @@ fn main() -> Result<()> {
             .to_string_lossy()
             .to_string()
     });
-    let config_path = get_mail_setting("config_path").unwrap_or_else(|| {
+    let config_path = mail_settings.config_path.unwrap_or_else(|| {
         dirs::home_dir()
             .expect(
                 "Could not find home dir, can't guess notmuch config path, \
@@ -1326,16 +1720,22 @@ fn main() -> Result<()> {
 
     tauri::Builder::default()
         .setup(|app| {
-            RUNTIME_STATE
-                .set(Mutex::new(RuntimeState::new(app.handle(), mail_store)))
-                .unwrap();
+            let mut runtime_state = RuntimeState::new(app.handle(), mail_store);
+            runtime_state.startup_messages = startup_messages;
+            RUNTIME_STATE.set(Mutex::new(runtime_state)).unwrap();
+            spawn_editor_supervisor();
+            sync::spawn_periodic_sync();
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             edit_node,
             change_node_text,
+            set_node_property,
             commit,
             get_node,
+            get_node_by_id,
+            get_backlinks,
+            resolve_node_path,
             get_node_title,
             get_node_folder_path,
             get_tree,
@@ -1344,13 +1744,20 @@ fn main() -> Result<()> {
             swap_node_with_previous,
             swap_node_with_next,
             delete_node,
+            list_trash,
+            restore_from_trash,
+            empty_trash,
             sort_children,
             compact_children,
             list_open_paths,
             date_to_path,
             create_calendar,
             reload_data,
+            list_aliases,
+            list_duplicate_aliases,
             edit_settings,
+            get_settings_schema,
+            take_startup_messages,
             get_tags,
             get_bookmarks,
             set_bookmarks,
@@ -1358,7 +1765,9 @@ fn main() -> Result<()> {
             get_mail_search_folders,
             find_next_empty_child,
             ripgrep_below_node,
+            query_nodes,
             find_first_below,
+            fuzzy_find_nodes,
             get_cached_node,
             set_cached_node,
             query_mail,
@@ -1383,12 +1792,103 @@ fn main() -> Result<()> {
             extract_tags,
             get_git_history,
             git_undo,
+            git_redo,
+            flush_commits,
+            sync_now,
+            list_conflicts,
+            list_leftover_edits,
+            cancel_edit,
+            resolve_leftover_edit,
+            resolve_conflict,
+            get_commit_diff,
+            get_node_history,
+            get_node_at_revision,
+            restore_node,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                //don't lose the changes still waiting in the commit queue
+                let ss = STORAGE.get().unwrap().lock().unwrap();
+                if let Err(e) = ss.flush_commits() {
+                    println!("failed to commit on exit: {:?}", e);
+                }
+            }
+        });
     signal_handle.close();
     signal_handle.close();
     println!("tauri ended");
     jt.join().ok();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn editor(id: u64, command: &str, wait: settings::WaitMode, dir: &Path) -> OpenEditor {
+        let temp_file = dir.join(format!("{id}.temp.adoc"));
+        std::fs::write(&temp_file, "changed").unwrap();
+        OpenEditor {
+            id,
+            path: format!("node {id}"),
+            temp_file,
+            org_content: "original".to_string(),
+            process: std::process::Command::new("sh")
+                .args(["-c", command])
+                .spawn()
+                .unwrap(),
+            window_title: String::new(),
+            remove_after: true,
+            wait,
+            saved: false,
+            base: None,
+        }
+    }
+
+    #[test]
+    fn finished_editors_are_taken_by_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut open_editors = vec![
+            editor(1, "exit 0", settings::WaitMode::Exit, dir.path()),
+            editor(2, "sleep 30", settings::WaitMode::Exit, dir.path()),
+            editor(3, "exit 1", settings::WaitMode::Exit, dir.path()),
+            editor(4, "sleep 30", settings::WaitMode::Save, dir.path()),
+        ];
+        open_editors[3].saved = true;
+        for entry in open_editors.iter_mut().filter(|e| e.id % 2 == 1) {
+            entry.process.wait().unwrap();
+        }
+
+        let finished = take_finished_editors(&mut open_editors);
+        let ended: Vec<(u64, Option<String>)> = finished
+            .iter()
+            .map(|(entry, end)| match end {
+                EditorEnd::Finished(result) => (entry.id, result.clone()),
+                EditorEnd::Lost => panic!("lost editor {}", entry.id),
+            })
+            .collect();
+        //a failed exit keeps nothing, a save ends the session with the editor still running
+        assert_eq!(
+            ended,
+            [
+                (1, Some("changed".to_string())),
+                (3, None),
+                (4, Some("changed".to_string()))
+            ]
+        );
+        let running: Vec<u64> = open_editors.iter().map(|e| e.id).collect();
+        assert_eq!(running, [2]);
+        assert!(take_finished_editors(&mut open_editors).is_empty());
+
+        for mut entry in open_editors
+            .into_iter()
+            .chain(finished.into_iter().map(|(entry, _)| entry))
+        {
+            entry.process.kill().ok();
+            entry.process.wait().ok();
+        }
+    }
+}
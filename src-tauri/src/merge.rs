@@ -0,0 +1,160 @@
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+
+// Line based three-way merge, for when a node was changed while it was open in the editor.
+// Changes of both sides are combined as long as they touch different lines -
+// overlapping (or adjacent) changes that differ are a conflict.
+
+struct Hunk<'a> {
+    //replaced range of base lines
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+    ours: bool,
+}
+
+fn hunks<'a>(base: &[&'a str], other: &[&'a str], ours: bool) -> Vec<Hunk<'a>> {
+    let mut res: Vec<Hunk> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match res.last_mut() {
+            //a delete directly followed by an insert is one change
+            Some(last) if last.end == old.start => {
+                last.end = old.end;
+                last.lines.extend_from_slice(&other[new]);
+            }
+            _ => res.push(Hunk {
+                start: old.start,
+                end: old.end,
+                lines: other[new].to_vec(),
+                ours,
+            }),
+        }
+    }
+    res
+}
+
+/// base[start..end] with the hunks (all within that range) applied
+fn apply<'a>(base: &[&'a str], start: usize, end: usize, hunks: &[&Hunk<'a>]) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        res.extend_from_slice(&base[pos..hunk.start]);
+        res.extend_from_slice(&hunk.lines);
+        pos = hunk.end;
+    }
+    res.extend_from_slice(&base[pos..end]);
+    res
+}
+
+/// Merge the changes from base to ours and from base to theirs.
+/// None if they conflict. Surrounding whitespace is ignored.
+pub(crate) fn merge3(base: &str, ours: &str, theirs: &str) -> Option<String> {
+    let base: Vec<&str> = base.trim().lines().collect();
+    let ours: Vec<&str> = ours.trim().lines().collect();
+    let theirs: Vec<&str> = theirs.trim().lines().collect();
+    let mut all = hunks(&base, &ours, true);
+    all.extend(hunks(&base, &theirs, false));
+    all.sort_by_key(|h| (h.start, h.end));
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut pos = 0;
+    let mut ii = 0;
+    while ii < all.len() {
+        //all hunks overlapping or touching the first one form a group
+        let start = all[ii].start;
+        let mut end = all[ii].end;
+        let mut jj = ii + 1;
+        while jj < all.len() && all[jj].start <= end {
+            end = end.max(all[jj].end);
+            jj += 1;
+        }
+        let group = &all[ii..jj];
+        let our_hunks: Vec<&Hunk> = group.iter().filter(|h| h.ours).collect();
+        let their_hunks: Vec<&Hunk> = group.iter().filter(|h| !h.ours).collect();
+        out.extend_from_slice(&base[pos..start]);
+        let ours_version = apply(&base, start, end, &our_hunks);
+        if their_hunks.is_empty() {
+            out.extend(ours_version);
+        } else {
+            let theirs_version = apply(&base, start, end, &their_hunks);
+            if !our_hunks.is_empty() && ours_version != theirs_version {
+                return None;
+            }
+            out.extend(theirs_version);
+        }
+        pos = end;
+        ii = jj;
+    }
+    out.extend_from_slice(&base[pos..]);
+    Some(out.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "one\ntwo\nthree\nfour\nfive\nsix";
+
+    #[test]
+    fn non_overlapping_changes_combine() {
+        let ours = "one\nTWO\nthree\nfour\nfive\nsix";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\nseven";
+        assert_eq!(
+            merge3(BASE, ours, theirs).unwrap(),
+            "one\nTWO\nthree\nfour\nFIVE\nsix\nseven"
+        );
+        //one side unchanged
+        assert_eq!(merge3(BASE, BASE, theirs).unwrap(), theirs);
+        assert_eq!(merge3(BASE, ours, BASE).unwrap(), ours);
+        //both sides made the same change
+        assert_eq!(merge3(BASE, ours, ours).unwrap(), ours);
+        //surrounding whitespace doesn't matter
+        assert_eq!(
+            merge3(&format!("\n{BASE}\n\n"), ours, &format!("{theirs}\n")).unwrap(),
+            "one\nTWO\nthree\nfour\nFIVE\nsix\nseven"
+        );
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        let ours = "one\ntwo\nTHREE\nfour\nfive\nsix";
+        let theirs = "one\ntwo\nthree (theirs)\nfour\nfive\nsix";
+        assert_eq!(merge3(BASE, ours, theirs), None);
+        //adjacent lines count as overlapping
+        let theirs = "one\ntwo\nthree\nFOUR\nfive\nsix";
+        assert_eq!(merge3(BASE, ours, theirs), None);
+    }
+
+    #[test]
+    fn inserts_at_the_same_position() {
+        let ours = "one\ntwo\nours\nthree\nfour\nfive\nsix";
+        let theirs = "one\ntwo\ntheirs\nthree\nfour\nfive\nsix";
+        assert_eq!(merge3(BASE, ours, theirs), None);
+        //the same insert on both sides is no conflict
+        assert_eq!(merge3(BASE, ours, ours).unwrap(), ours);
+        //appending on both sides
+        assert_eq!(
+            merge3(BASE, &format!("{BASE}\nours"), &format!("{BASE}\ntheirs")),
+            None
+        );
+    }
+
+    #[test]
+    fn delete_against_edit() {
+        let deleted = "one\ntwo\nfour\nfive\nsix";
+        let edited = "one\ntwo\nTHREE\nfour\nfive\nsix";
+        assert_eq!(merge3(BASE, deleted, edited), None);
+        assert_eq!(merge3(BASE, edited, deleted), None);
+        //a delete elsewhere is fine
+        let edited = "one\ntwo\nthree\nfour\nFIVE\nsix";
+        assert_eq!(
+            merge3(BASE, deleted, edited).unwrap(),
+            "one\ntwo\nfour\nFIVE\nsix"
+        );
+        //both deleted the same line
+        assert_eq!(merge3(BASE, deleted, deleted).unwrap(), deleted);
+    }
+}
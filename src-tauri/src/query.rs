@@ -0,0 +1,541 @@
+use anyhow::{bail, Context, Result};
+
+use crate::storage::{Node, PropertyValue, TreePath};
+
+// A small query language over the node tree.
+//
+//   query := or
+//   or    := and ('OR' and)*
+//   and   := unary ('AND'? unary)*       -- juxtaposition means AND
+//   unary := 'NOT' unary | '(' or ')' | term
+//   term  := tag:#project                -- node has the tag ('#' optional)
+//          | below:B                     -- node is a (grand)child of B (human path, id:... )
+//          | title:word  | text:word     -- case insensitive substring in title / whole text
+//          | has:key                     -- node has the property
+//          | key:value                   -- property equals value
+//          | key(=|!=|<|<=|>|>=)value    -- typed property comparison, e.g. modified>2026-01-01
+//          | word | "some phrase"        -- same as text:...
+//
+// Values containing spaces can be quoted: title:"weekly review"
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Term {
+    Tag(String),
+    Below(TreePath),
+    Title(String),
+    Text(String),
+    Has(String),
+    Property { key: String, op: Op, value: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if c == '"' {
+                        chars.next();
+                        let mut closed = false;
+                        while let Some(c) = chars.next() {
+                            if c == '"' {
+                                closed = true;
+                                break;
+                            }
+                            word.push(c);
+                        }
+                        if !closed {
+                            bail!("Unterminated quote in query");
+                        }
+                        quoted = true;
+                    } else if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    } else {
+                        word.push(c);
+                        chars.next();
+                    }
+                }
+                tokens.push(match &word[..] {
+                    "AND" if !quoted => Token::And,
+                    "OR" if !quoted => Token::Or,
+                    "NOT" if !quoted => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    resolve_path: &'a dyn Fn(&str) -> Result<TreePath>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut left = self.parse_and()?;
+        while let Some(Token::Or) = self.peek() {
+            self.next();
+            let right = self.parse_and()?;
+            left = Query::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut left = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Open) | Some(Token::Not) | Some(Token::Word(_)) => {}
+                _ => break,
+            }
+            let right = self.parse_unary()?;
+            left = Query::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query> {
+        match self.next() {
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => bail!("Missing ')' in query"),
+                }
+            }
+            Some(Token::Word(word)) => Ok(Query::Term(self.parse_term(&word)?)),
+            Some(Token::Close) => bail!("Unexpected ')' in query"),
+            Some(Token::And) | Some(Token::Or) => {
+                bail!("AND/OR need an expression on both sides")
+            }
+            None => bail!("Query ended unexpectedly"),
+        }
+    }
+
+    fn parse_term(&self, word: &str) -> Result<Term> {
+        //the first operator in the word decides - values may well contain ':' (times)
+        let ops = [
+            ("<=", Some(Op::LessEq)),
+            (">=", Some(Op::GreaterEq)),
+            ("!=", Some(Op::NotEq)),
+            ("=", Some(Op::Eq)),
+            ("<", Some(Op::Less)),
+            (">", Some(Op::Greater)),
+            (":", None),
+        ];
+        let first = ops
+            .iter()
+            .filter_map(|(s, op)| word.find(s).map(|pos| (pos, *s, *op)))
+            .min_by_key(|(pos, s, _)| (*pos, std::cmp::Reverse(s.len())));
+        let (pos, op_str, op) = match first {
+            Some(x) if x.0 > 0 => x,
+            _ => return Ok(Term::Text(word.to_lowercase())),
+        };
+        let key = word[..pos].to_lowercase();
+        let value = &word[pos + op_str.len()..];
+        if value.is_empty() {
+            bail!("No value given for '{}' in query", key);
+        }
+        Ok(match (op, &key[..]) {
+            (None, "tag") => Term::Tag(if value.starts_with('#') {
+                value.to_string()
+            } else {
+                format!("#{}", value)
+            }),
+            (None, "below") => Term::Below(
+                (self.resolve_path)(value)
+                    .with_context(|| format!("could not resolve 'below:{}'", value))?,
+            ),
+            (None, "title") => Term::Title(value.to_lowercase()),
+            (None, "text") => Term::Text(value.to_lowercase()),
+            (None, "has") => Term::Has(value.to_lowercase()),
+            (None, _) => Term::Property {
+                key,
+                op: Op::Eq,
+                value: value.to_string(),
+            },
+            (Some(op), _) => {
+                if op != Op::Eq && op != Op::NotEq {
+                    //fail early on 'priority>high'
+                    PropertyValue::parse(&key, value)?;
+                }
+                Term::Property {
+                    key,
+                    op,
+                    value: value.to_string(),
+                }
+            }
+        })
+    }
+}
+
+fn compare(a: &PropertyValue, b: &PropertyValue) -> Option<std::cmp::Ordering> {
+    use PropertyValue::*;
+    match (a, b) {
+        (Date(a), Date(b)) => Some(a.cmp(b)),
+        (DateTime(a), DateTime(b)) => Some(a.cmp(b)),
+        (DateTime(a), Date(b)) => Some(a.date().cmp(b)),
+        (Date(a), DateTime(b)) => Some(a.cmp(&b.date())),
+        (Number(a), Number(b)) => Some(a.cmp(b)),
+        (Text(a), Text(b)) => Some(a.to_lowercase().cmp(&b.to_lowercase())),
+        _ => None,
+    }
+}
+
+impl Term {
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            Term::Tag(tag) => node.get_tags().iter().any(|t| t == tag),
+            Term::Below(path) => node.path.starts_with(path) && &node.path != path,
+            Term::Title(q) => node.header.title.to_lowercase().contains(q),
+            Term::Text(q) => node.raw.to_lowercase().contains(q),
+            Term::Has(key) => node.properties.contains_key(key),
+            Term::Property { key, op, value } => {
+                let actual = match node.properties.get(key) {
+                    Some(actual) => actual,
+                    None => return *op == Op::NotEq,
+                };
+                let expected = match PropertyValue::parse(key, value) {
+                    Ok(expected) => expected,
+                    Err(_) => PropertyValue::Text(value.to_string()),
+                };
+                let ord = compare(actual, &expected).or_else(|| {
+                    //different types (e.g. a custom key that's sometimes a number) - compare as text
+                    compare(
+                        &PropertyValue::Text(actual.to_string()),
+                        &PropertyValue::Text(value.to_string()),
+                    )
+                });
+                use std::cmp::Ordering::*;
+                match (op, ord) {
+                    (Op::Eq, Some(o)) => o == Equal,
+                    (Op::NotEq, Some(o)) => o != Equal,
+                    (Op::Less, Some(o)) => o == Less,
+                    (Op::LessEq, Some(o)) => o != Greater,
+                    (Op::Greater, Some(o)) => o == Greater,
+                    (Op::GreaterEq, Some(o)) => o != Less,
+                    (_, None) => false,
+                }
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Parse a query. resolve_path turns the argument of below: into a TreePath.
+    pub(crate) fn parse(
+        input: &str,
+        resolve_path: &dyn Fn(&str) -> Result<TreePath>,
+    ) -> Result<Query> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            bail!("Empty query");
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            resolve_path,
+        };
+        let query = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            bail!("Unexpected {:?} in query", parser.tokens[parser.pos]);
+        }
+        Ok(query)
+    }
+
+    /// If the query can only match below a certain path (a top level below: term),
+    /// return it, so the caller can restrict the search to that subtree
+    pub(crate) fn restricted_to(&self) -> Option<&TreePath> {
+        match self {
+            Query::And(a, b) => a.restricted_to().or_else(|| b.restricted_to()),
+            Query::Term(Term::Below(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn matches(&self, node: &Node) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(node) && b.matches(node),
+            Query::Or(a, b) => a.matches(node) || b.matches(node),
+            Query::Not(a) => !a.matches(node),
+            Query::Term(t) => t.matches(node),
+        }
+    }
+
+    /// The text terms that are not negated - used to pick the lines to show for a hit
+    fn positive_text_terms(&self, negated: bool, out: &mut Vec<String>) {
+        match self {
+            Query::And(a, b) | Query::Or(a, b) => {
+                a.positive_text_terms(negated, out);
+                b.positive_text_terms(negated, out);
+            }
+            Query::Not(a) => a.positive_text_terms(!negated, out),
+            Query::Term(Term::Text(q)) | Query::Term(Term::Title(q)) if !negated => {
+                out.push(q.clone())
+            }
+            Query::Term(_) => {}
+        }
+    }
+
+    /// (1 based line number, line) of the lines of node containing a searched for text
+    pub(crate) fn matching_lines(&self, node: &Node) -> Vec<(u32, String)> {
+        let mut terms = Vec::new();
+        self.positive_text_terms(false, &mut terms);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        node.raw
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let line = line.to_lowercase();
+                terms.iter().any(|t| line.contains(t))
+            })
+            .map(|(ii, line)| (ii as u32 + 1, line.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Query> {
+        Query::parse(input, &|p| TreePath::from_human(p))
+    }
+
+    fn term(t: Term) -> Box<Query> {
+        Box::new(Query::Term(t))
+    }
+
+    fn text(q: &str) -> Term {
+        Term::Text(q.to_string())
+    }
+
+    fn node(path: &str, raw: &str) -> Node {
+        Node::from_raw(
+            TreePath::from_human(path).unwrap(),
+            String::new(),
+            raw.to_string(),
+        )
+    }
+
+    #[test]
+    fn precedence() {
+        //NOT binds tighter than AND, AND tighter than OR
+        assert_eq!(
+            parse("a OR NOT b c").unwrap(),
+            Query::Or(
+                term(text("a")),
+                Box::new(Query::And(
+                    Box::new(Query::Not(term(text("b")))),
+                    term(text("c"))
+                ))
+            )
+        );
+        assert_eq!(
+            parse("(a OR b) AND c").unwrap(),
+            Query::And(
+                Box::new(Query::Or(term(text("a")), term(text("b")))),
+                term(text("c"))
+            )
+        );
+        //left associative
+        assert_eq!(
+            parse("a OR b OR c").unwrap(),
+            Query::Or(
+                Box::new(Query::Or(term(text("a")), term(text("b")))),
+                term(text("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn terms() {
+        assert_eq!(
+            parse("tag:project").unwrap(),
+            Query::Term(Term::Tag("#project".into()))
+        );
+        assert_eq!(
+            parse("tag:#project").unwrap(),
+            Query::Term(Term::Tag("#project".into()))
+        );
+        assert_eq!(
+            parse("below:BC").unwrap(),
+            Query::Term(Term::Below(TreePath::from(vec![1u32, 2])))
+        );
+        assert_eq!(
+            parse("title:\"Weekly Review\"").unwrap(),
+            Query::Term(Term::Title("weekly review".into()))
+        );
+        assert_eq!(
+            parse("\"AND\"").unwrap(),
+            Query::Term(Term::Text("and".into()))
+        );
+        assert_eq!(
+            parse("has:Due").unwrap(),
+            Query::Term(Term::Has("due".into()))
+        );
+        assert_eq!(
+            parse("modified>=2026-01-01").unwrap(),
+            Query::Term(Term::Property {
+                key: "modified".into(),
+                op: Op::GreaterEq,
+                value: "2026-01-01".into()
+            })
+        );
+        //the first operator decides, times keep their ':'
+        assert_eq!(
+            parse("modified>2026-01-01T10:00:00").unwrap(),
+            Query::Term(Term::Property {
+                key: "modified".into(),
+                op: Op::Greater,
+                value: "2026-01-01T10:00:00".into()
+            })
+        );
+        assert_eq!(
+            parse("status:open").unwrap(),
+            Query::Term(Term::Property {
+                key: "status".into(),
+                op: Op::Eq,
+                value: "open".into()
+            })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for (input, message) in [
+            ("", "Empty query"),
+            ("   ", "Empty query"),
+            ("\"open", "Unterminated quote"),
+            ("(a OR b", "Missing ')'"),
+            ("a)", "Unexpected Close"),
+            (")", "Unexpected ')'"),
+            ("a AND", "ended unexpectedly"),
+            ("OR a", "need an expression on both sides"),
+            ("a AND OR b", "need an expression on both sides"),
+            ("NOT", "ended unexpectedly"),
+            ("status:", "No value given for 'status'"),
+            ("priority>high", "priority must be a number"),
+            ("due<tomorrow", "due must be a date"),
+            ("below:a", "could not resolve 'below:a'"),
+        ] {
+            let err = parse(input).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains(message),
+                "{:?}: {:#}",
+                input,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn evaluation() {
+        let alpha = node(
+            "BA",
+            ":status: open\n:priority: 2\n:modified: 2026-03-01 10:00\nAlpha #project\n\nSome Text",
+        );
+        let beta = node("C", ":status: done\n:due: 2025-12-31\nBeta #project #done");
+        let matching = |q: &str| -> Vec<String> {
+            let query = parse(q).unwrap();
+            [&alpha, &beta]
+                .iter()
+                .filter(|n| query.matches(n))
+                .map(|n| n.header.title.clone())
+                .collect()
+        };
+        assert_eq!(
+            matching("tag:#project AND below:B AND NOT tag:#done AND modified>2026-01-01"),
+            ["Alpha #project"]
+        );
+        assert_eq!(matching("below:B"), ["Alpha #project"]);
+        //below: is strictly below
+        assert!(matching("below:BA").is_empty());
+        assert_eq!(
+            matching("tag:done OR priority<3"),
+            ["Alpha #project", "Beta #project #done"]
+        );
+        assert_eq!(matching("modified<2026-03-02"), ["Alpha #project"]);
+        assert_eq!(matching("modified>2026-03-01"), Vec::<String>::new());
+        assert_eq!(matching("status!=open"), ["Beta #project #done"]);
+        //a missing property only matches !=
+        assert_eq!(matching("priority!=2"), ["Beta #project #done"]);
+        assert_eq!(matching("STATUS:OPEN"), ["Alpha #project"]);
+        assert_eq!(matching("has:due"), ["Beta #project #done"]);
+        assert_eq!(matching("\"some text\""), ["Alpha #project"]);
+        assert_eq!(matching("title:beta"), ["Beta #project #done"]);
+    }
+
+    #[test]
+    fn matching_lines_and_restriction() {
+        let alpha = node("BA", "Alpha\n\nfirst line\nsecond Line");
+        let query = parse("line NOT second below:B").unwrap();
+        assert_eq!(
+            query.matching_lines(&alpha),
+            vec![(3, "first line".into()), (4, "second Line".into())]
+        );
+        assert_eq!(query.restricted_to(), Some(&TreePath::from(vec![1u32])));
+        assert_eq!(parse("a OR below:B").unwrap().restricted_to(), None);
+    }
+}
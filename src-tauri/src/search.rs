@@ -0,0 +1,410 @@
+use std::collections::{HashMap, HashSet};
+
+// In-process full text index over the nodes, keyed by node id
+// (so moving nodes around does not invalidate it).
+//
+// Search syntax: words are and-ed, "quoted phrases" have to appear as is,
+// a trailing * makes a word a prefix match (proj*).
+// Matching is on whole words, case insensitive.
+
+//BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Debug, Default)]
+pub(crate) struct SearchIndex {
+    //term -> node id -> positions (word index) of the term in that node
+    postings: HashMap<String, HashMap<String, Vec<u32>>>,
+    //node id -> its distinct terms, so removing a node only touches those
+    doc_terms: HashMap<String, HashSet<String>>,
+    //node id -> number of words
+    doc_lengths: HashMap<String, u32>,
+    total_length: u64,
+}
+
+/// (start, end, lowercased word) for every word in text
+fn words_with_spans(text: &str) -> Vec<(usize, usize, String)> {
+    let mut res = Vec::new();
+    let mut start = None;
+    for (pos, c) in text.char_indices() {
+        if c.is_alphanumeric() || c == '_' {
+            if start.is_none() {
+                start = Some(pos);
+            }
+        } else if let Some(s) = start.take() {
+            res.push((s, pos, text[s..pos].to_lowercase()));
+        }
+    }
+    if let Some(s) = start {
+        res.push((s, text.len(), text[s..].to_lowercase()));
+    }
+    res
+}
+
+fn words(text: &str) -> Vec<String> {
+    words_with_spans(text).into_iter().map(|x| x.2).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    Word(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+impl Element {
+    fn matches_word(&self, word: &str) -> bool {
+        match self {
+            Element::Word(w) => w == word,
+            Element::Prefix(p) => word.starts_with(p),
+            Element::Phrase(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SearchQuery {
+    elements: Vec<Element>,
+}
+
+impl SearchQuery {
+    pub(crate) fn parse(query: &str) -> SearchQuery {
+        let mut elements = Vec::new();
+        for (ii, part) in query.split('"').enumerate() {
+            if ii % 2 == 1 {
+                //inside quotes
+                let phrase = words(part);
+                match phrase.len() {
+                    0 => {}
+                    1 => elements.push(Element::Word(phrase[0].clone())),
+                    _ => elements.push(Element::Phrase(phrase)),
+                }
+            } else {
+                for raw in part.split_whitespace() {
+                    let prefix = raw.ends_with('*');
+                    for w in words(raw) {
+                        elements.push(if prefix {
+                            Element::Prefix(w)
+                        } else {
+                            Element::Word(w)
+                        });
+                    }
+                }
+            }
+        }
+        SearchQuery { elements }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// (1 based line number, line) for all lines containing any part of the query.
+    /// With only_matching, each matched word/phrase is reported on its own (like rg -o)
+    pub(crate) fn matching_lines(&self, text: &str, only_matching: bool) -> Vec<(u32, String)> {
+        let mut res = Vec::new();
+        for (ii, line) in text.lines().enumerate() {
+            let line_words = words_with_spans(line);
+            let mut hits: Vec<(usize, usize)> = Vec::new();
+            for element in self.elements.iter() {
+                match element {
+                    Element::Phrase(phrase) => {
+                        for start in 0..line_words.len() {
+                            if line_words.len() - start >= phrase.len()
+                                && line_words[start..start + phrase.len()]
+                                    .iter()
+                                    .zip(phrase.iter())
+                                    .all(|(a, b)| &a.2 == b)
+                            {
+                                hits.push((
+                                    line_words[start].0,
+                                    line_words[start + phrase.len() - 1].1,
+                                ));
+                            }
+                        }
+                    }
+                    _ => {
+                        for (s, e, w) in line_words.iter() {
+                            if element.matches_word(w) {
+                                hits.push((*s, *e));
+                            }
+                        }
+                    }
+                }
+            }
+            if hits.is_empty() {
+                continue;
+            }
+            if only_matching {
+                hits.sort();
+                for (s, e) in hits {
+                    res.push((ii as u32 + 1, line[s..e].to_string()));
+                }
+            } else {
+                res.push((ii as u32 + 1, line.to_string()));
+            }
+        }
+        res
+    }
+}
+
+impl SearchIndex {
+    pub(crate) fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.postings.clear();
+        self.doc_terms.clear();
+        self.doc_lengths.clear();
+        self.total_length = 0;
+    }
+
+    /// (re)index the text of a node
+    pub(crate) fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let words = words(text);
+        for (pos, word) in words.iter().enumerate() {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .entry(id.to_string())
+                .or_default()
+                .push(pos as u32);
+        }
+        self.doc_lengths.insert(id.to_string(), words.len() as u32);
+        self.total_length += words.len() as u64;
+        self.doc_terms
+            .insert(id.to_string(), words.into_iter().collect());
+    }
+
+    pub(crate) fn remove(&mut self, id: &str) {
+        if let Some(len) = self.doc_lengths.remove(id) {
+            self.total_length -= len as u64;
+        }
+        for term in self.doc_terms.remove(id).unwrap_or_default() {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(id);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// the postings for a (non phrase) element, merged over all matching terms for prefixes
+    fn postings_for(&self, element: &Element) -> Vec<&HashMap<String, Vec<u32>>> {
+        match element {
+            Element::Word(w) => self.postings.get(w).into_iter().collect(),
+            Element::Prefix(p) => self
+                .postings
+                .iter()
+                .filter(|(term, _)| term.starts_with(p))
+                .map(|(_, docs)| docs)
+                .collect(),
+            Element::Phrase(_) => Vec::new(),
+        }
+    }
+
+    fn docs_with_phrase(&self, phrase: &[String]) -> HashSet<String> {
+        let per_word: Vec<&HashMap<String, Vec<u32>>> =
+            match phrase.iter().map(|w| self.postings.get(w)).collect() {
+                Some(x) => x,
+                None => return HashSet::new(),
+            };
+        per_word[0]
+            .iter()
+            .filter(|(id, starts)| {
+                starts.iter().any(|start| {
+                    per_word[1..].iter().enumerate().all(|(offset, docs)| {
+                        docs.get(*id)
+                            .map(|positions| {
+                                positions
+                                    .binary_search(&(start + offset as u32 + 1))
+                                    .is_ok()
+                            })
+                            .unwrap_or(false)
+                    })
+                })
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// All node ids matching every element of the query, best (BM25) first
+    pub(crate) fn search(&self, query: &SearchQuery) -> Vec<(String, f64)> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut candidates: Option<HashSet<String>> = None;
+        for element in query.elements.iter() {
+            let docs: HashSet<String> = match element {
+                Element::Phrase(phrase) => self.docs_with_phrase(phrase),
+                _ => self
+                    .postings_for(element)
+                    .iter()
+                    .flat_map(|docs| docs.keys().cloned())
+                    .collect(),
+            };
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&docs).cloned().collect(),
+                None => docs,
+            });
+        }
+        let candidates = candidates.unwrap_or_default();
+
+        let doc_count = self.doc_lengths.len().max(1) as f64;
+        let avg_len = (self.total_length as f64 / doc_count).max(1.0);
+        let mut scored: Vec<(String, f64)> = candidates
+            .into_iter()
+            .map(|id| {
+                let len = *self.doc_lengths.get(&id).unwrap_or(&0) as f64;
+                let mut score = 0.0;
+                for element in query.elements.iter() {
+                    let single;
+                    let terms: Vec<&HashMap<String, Vec<u32>>> = match element {
+                        Element::Phrase(phrase) => {
+                            phrase.iter().filter_map(|w| self.postings.get(w)).collect()
+                        }
+                        _ => {
+                            single = self.postings_for(element);
+                            single
+                        }
+                    };
+                    for docs in terms {
+                        let tf = docs.get(&id).map(|x| x.len()).unwrap_or(0) as f64;
+                        if tf == 0.0 {
+                            continue;
+                        }
+                        let df = docs.len() as f64;
+                        let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+                        score += idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                    }
+                }
+                (id, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        scored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(docs: &[(&str, &str)]) -> SearchIndex {
+        let mut index = SearchIndex::new();
+        for (id, text) in docs {
+            index.insert(id, text);
+        }
+        index
+    }
+
+    fn ids(index: &SearchIndex, query: &str) -> Vec<String> {
+        index
+            .search(&SearchQuery::parse(query))
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn tokenizer() {
+        assert_eq!(
+            words("Hello, wörld! snake_case x2 #tag <<florg:BC>>"),
+            ["hello", "wörld", "snake_case", "x2", "tag", "florg", "bc"]
+        );
+        assert_eq!(
+            words_with_spans("  ab-cd"),
+            vec![(2, 4, "ab".to_string()), (5, 7, "cd".to_string())]
+        );
+        assert!(words(" ,.- ").is_empty());
+    }
+
+    #[test]
+    fn query_parsing() {
+        let q = SearchQuery::parse("Proj* \"Weekly  Review\" \"single\" plain");
+        assert_eq!(
+            q.elements,
+            vec![
+                Element::Prefix("proj".into()),
+                Element::Phrase(vec!["weekly".into(), "review".into()]),
+                Element::Word("single".into()),
+                Element::Word("plain".into()),
+            ]
+        );
+        assert!(SearchQuery::parse(" \"\" ").is_empty());
+    }
+
+    #[test]
+    fn words_prefixes_and_phrases() {
+        let index = index(&[
+            ("a", "the weekly review of projects"),
+            ("b", "review the weekly numbers"),
+            ("c", "a project plan"),
+        ]);
+        //the shorter text ranks first
+        assert_eq!(ids(&index, "weekly review"), ["b", "a"]);
+        assert_eq!(ids(&index, "\"weekly review\""), ["a"]);
+        assert_eq!(ids(&index, "\"review weekly\""), Vec::<String>::new());
+        assert_eq!(ids(&index, "proj*"), ["c", "a"]);
+        //whole words only
+        assert_eq!(ids(&index, "proj"), Vec::<String>::new());
+        assert_eq!(ids(&index, "PLAN"), ["c"]);
+        assert!(ids(&index, "").is_empty());
+    }
+
+    #[test]
+    fn ranking() {
+        let index = index(&[
+            (
+                "long",
+                "rust is mentioned once in this rather long text about other things",
+            ),
+            ("often", "rust rust rust"),
+            ("short", "rust once"),
+            ("none", "nothing here"),
+        ]);
+        assert_eq!(ids(&index, "rust"), ["often", "short", "long"]);
+        //equal scores are ordered by id
+        let index = super::tests::index(&[("b", "same"), ("a", "same")]);
+        assert_eq!(ids(&index, "same"), ["a", "b"]);
+    }
+
+    #[test]
+    fn remove_and_reinsert() {
+        let mut index = index(&[("a", "alpha beta"), ("b", "beta gamma")]);
+        index.insert("a", "delta");
+        assert!(ids(&index, "alpha").is_empty());
+        assert!(!index.postings.contains_key("alpha"));
+        assert_eq!(ids(&index, "beta"), ["b"]);
+        index.remove("b");
+        assert_eq!(index.postings.keys().collect::<Vec<_>>(), ["delta"]);
+        assert_eq!(index.total_length, 1);
+        index.remove("unknown");
+        assert_eq!(ids(&index, "delta"), ["a"]);
+    }
+
+    #[test]
+    fn matching_lines() {
+        let q = SearchQuery::parse("\"big cat\" dog*");
+        let text = "a big cat\nno match\ndogs and a Big Cat";
+        assert_eq!(
+            q.matching_lines(text, false),
+            vec![(1, "a big cat".into()), (3, "dogs and a Big Cat".into())]
+        );
+        assert_eq!(
+            q.matching_lines(text, true),
+            vec![
+                (1, "big cat".into()),
+                (3, "dogs".into()),
+                (3, "Big Cat".into())
+            ]
+        );
+    }
+}
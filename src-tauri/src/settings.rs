@@ -0,0 +1,484 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+// The typed view of settings.toml.
+//
+// Storage keeps the toml_edit::Document next to it, so writing settings back
+// (bookmarks...) keeps the user's comments and formatting. Every key has a default,
+// a missing file is the same as an empty one.
+// When adding a key, also describe it in schema().
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Settings {
+    //tag -> css color
+    pub tags: Option<HashMap<String, String>>,
+    //key -> node path or @alias
+    pub bookmarks: Option<HashMap<String, String>>,
+    pub nav: Option<HashMap<String, String>>,
+    //name -> notmuch query
+    pub mail_queries: Option<HashMap<String, String>>,
+    pub mail_tags: Option<HashMap<String, String>>,
+    pub mail: MailSettings,
+    pub chatgpt: ChatGptSettings,
+    pub git: GitSettings,
+    pub sync: Option<SyncSettings>,
+    pub editor: EditorSettings,
+    pub terminal: TerminalSettings,
+    pub properties: PropertySettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MailSettings {
+    pub mail_dir: Option<String>,
+    pub config_path: Option<String>,
+    pub attachment_dir: String,
+    //authors not shown in thread summaries (i.e. yourself)
+    pub filtered_authors: Vec<String>,
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        MailSettings {
+            mail_dir: None,
+            config_path: None,
+            attachment_dir: "~/attachments".to_string(),
+            filtered_authors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ChatGptSettings {
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GitSettings {
+    //how long to collect changes into one commit
+    pub commit_window_seconds: u64,
+}
+
+impl Default for GitSettings {
+    fn default() -> Self {
+        GitSettings {
+            commit_window_seconds: 2,
+        }
+    }
+}
+
+impl GitSettings {
+    pub(crate) fn commit_window(&self) -> Duration {
+        Duration::from_secs(self.commit_window_seconds)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SyncSettings {
+    pub remote: String,
+    //creates/updates the remote
+    pub url: Option<String>,
+    //remote branch, default: the current branch
+    pub branch: Option<String>,
+    //background sync, 0 = only on sync_now
+    pub interval_minutes: u64,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        SyncSettings {
+            remote: "origin".to_string(),
+            url: None,
+            branch: None,
+            interval_minutes: 0,
+        }
+    }
+}
+
+impl SyncSettings {
+    pub(crate) fn interval(&self) -> Option<Duration> {
+        match self.interval_minutes {
+            0 => None,
+            minutes => Some(Duration::from_secs(minutes * 60)),
+        }
+    }
+}
+
+/// When is an edit done?
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WaitMode {
+    //when the editor process exits (terminal editors, 'code --wait')
+    Exit,
+    //on the first save - for GUI editors that fork and return right away
+    Save,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct EditorSettings {
+    //{file} and {line} are replaced, the file is appended if there's no {file}
+    pub command: Vec<String>,
+    //the editor command is appended to this - [] for GUI editors
+    pub terminal: Vec<String>,
+    pub wait: WaitMode,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        EditorSettings {
+            command: vec![
+                "nvim".to_string(),
+                "+{line}".to_string(),
+                "{file}".to_string(),
+            ],
+            terminal: vec!["kitty".to_string(), "--".to_string()],
+            wait: WaitMode::Exit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TerminalSettings {
+    //started in the node's folder
+    pub command: Vec<String>,
+}
+
+impl Default for TerminalSettings {
+    fn default() -> Self {
+        TerminalSettings {
+            command: vec!["kitty".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PropertySettings {
+    //stamp :created: / :modified: into the node text on every save
+    pub timestamps: bool,
+}
+
+/// What's wrong with a settings.toml - key is dotted (git.commit_window_seconds),
+/// line 1 based
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct SettingsError {
+    pub key: Option<String>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "settings.toml")?;
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, " ({})", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+fn line_of_offset(raw: &str, offset: usize) -> usize {
+    raw[..offset.min(raw.len())].matches('\n').count() + 1
+}
+
+/// best effort: the line that defines the dotted key
+fn line_of_key(raw: &str, key: &str) -> Option<usize> {
+    let (table, name) = match key.rsplit_once('.') {
+        Some((table, name)) => (Some(table), name),
+        None => (None, key),
+    };
+    let mut in_table = table.is_none();
+    for (ii, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = line.trim_matches(|c| c == '[' || c == ']').trim();
+            in_table = Some(header) == table;
+            continue;
+        }
+        if in_table
+            && line
+                .split_once('=')
+                .map(|(k, _)| k.trim().trim_matches('"') == name)
+                .unwrap_or(false)
+        {
+            return Some(ii + 1);
+        }
+    }
+    None
+}
+
+impl Settings {
+    /// Parse and validate settings.toml contents
+    pub(crate) fn parse(raw: &str) -> Result<(toml_edit::Document, Settings), SettingsError> {
+        let de = toml::Deserializer::new(raw);
+        let settings: Settings = serde_path_to_error::deserialize(de).map_err(|e| {
+            let path = e.path().to_string();
+            let inner = e.into_inner();
+            SettingsError {
+                line: inner.span().map(|span| line_of_offset(raw, span.start)),
+                key: if path == "." { None } else { Some(path) },
+                message: inner.message().to_string(),
+            }
+        })?;
+        settings
+            .validate()
+            .map_err(|(key, message)| SettingsError {
+                line: line_of_key(raw, key),
+                key: Some(key.to_string()),
+                message,
+            })?;
+        let document = raw
+            .parse::<toml_edit::Document>()
+            .map_err(|e| SettingsError {
+                key: None,
+                line: None,
+                message: e.to_string(),
+            })?;
+        Ok((document, settings))
+    }
+
+    /// Parse what can be parsed - top level keys/tables that don't parse or validate
+    /// keep their defaults. Returns the error of the whole file along,
+    /// and the document as is (if it's valid toml), so storing doesn't lose anything.
+    pub(crate) fn parse_lenient(
+        raw: &str,
+    ) -> (toml_edit::Document, Settings, Option<SettingsError>) {
+        let error = match Self::parse(raw) {
+            Ok((document, settings)) => return (document, settings, None),
+            Err(e) => e,
+        };
+        let document = raw.parse::<toml_edit::Document>().unwrap_or_default();
+        let mut valid = toml_edit::Document::new();
+        for (key, item) in document.iter() {
+            let mut single = toml_edit::Document::new();
+            single.insert(key, item.clone());
+            if Self::parse(&single.to_string()).is_ok() {
+                valid.insert(key, item.clone());
+            }
+        }
+        let settings = Self::parse(&valid.to_string())
+            .map(|(_, settings)| settings)
+            .unwrap_or_default();
+        (document, settings, Some(error))
+    }
+
+    /// checks beyond the types - (key, what's wrong)
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        if self.git.commit_window_seconds > 3600 {
+            return Err((
+                "git.commit_window_seconds",
+                "must be at most 3600 - changes are only committed after the window".to_string(),
+            ));
+        }
+        if self.editor.command.is_empty() {
+            return Err(("editor.command", "must not be empty".to_string()));
+        }
+        if self.terminal.command.is_empty() {
+            return Err(("terminal.command", "must not be empty".to_string()));
+        }
+        if let Some(sync) = &self.sync {
+            if sync.remote.trim().is_empty() {
+                return Err(("sync.remote", "must not be empty".to_string()));
+            }
+            if sync.url.as_deref().map(|u| u.trim().is_empty()) == Some(true) {
+                return Err((
+                    "sync.url",
+                    "must not be empty - leave it out instead".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One settings key, for the settings UI
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SettingInfo {
+    pub key: &'static str,
+    //string, integer, boolean, [string], {string: string}
+    pub kind: &'static str,
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+pub(crate) fn schema() -> Vec<SettingInfo> {
+    let info = |key, kind, default, description| SettingInfo {
+        key,
+        kind,
+        default,
+        description,
+    };
+    vec![
+        info(
+            "editor.command",
+            "[string]",
+            "[\"nvim\", \"+{line}\", \"{file}\"]",
+            "The editor - {file} and {line} are replaced",
+        ),
+        info(
+            "editor.terminal",
+            "[string]",
+            "[\"kitty\", \"--\"]",
+            "Terminal to run the editor in, [] for GUI editors",
+        ),
+        info(
+            "editor.wait",
+            "string",
+            "exit",
+            "When an edit is done: 'exit' of the editor, or the first 'save' (for editors that fork)",
+        ),
+        info(
+            "terminal.command",
+            "[string]",
+            "[\"kitty\"]",
+            "Terminal opened in a node's folder",
+        ),
+        info(
+            "tags",
+            "{string: string}",
+            "",
+            "Tag -> color, for highlighting #tags",
+        ),
+        info(
+            "bookmarks",
+            "{string: string}",
+            "",
+            "Key -> node path or @alias (survives moves)",
+        ),
+        info(
+            "nav",
+            "{string: string}",
+            "",
+            "Navigation shortcuts, key -> node path",
+        ),
+        info(
+            "mail_queries",
+            "{string: string}",
+            "",
+            "Mail folders, name -> notmuch query",
+        ),
+        info(
+            "mail_tags",
+            "{string: string}",
+            "",
+            "Mail tag -> key to toggle it",
+        ),
+        info("mail.mail_dir", "string", "~/mail", "The maildir"),
+        info(
+            "mail.config_path",
+            "string",
+            "~/.notmuch-config",
+            "The notmuch config file",
+        ),
+        info(
+            "mail.attachment_dir",
+            "string",
+            "~/attachments",
+            "Where attachments are stored - emptied on each use",
+        ),
+        info(
+            "mail.filtered_authors",
+            "[string]",
+            "[]",
+            "Authors left out of thread summaries (i.e. yourself)",
+        ),
+        info(
+            "chatgpt.api_key",
+            "string",
+            "",
+            "OpenAI API key, enables the chatgpt features",
+        ),
+        info(
+            "git.commit_window_seconds",
+            "integer",
+            "2",
+            "How long to collect changes into one commit (max 3600)",
+        ),
+        info(
+            "properties.timestamps",
+            "boolean",
+            "false",
+            "Write :created: / :modified: properties into a node's text when it is saved",
+        ),
+        info("sync.remote", "string", "origin", "Git remote to sync with"),
+        info(
+            "sync.url",
+            "string",
+            "",
+            "Url of the remote - creates/updates it",
+        ),
+        info(
+            "sync.branch",
+            "string",
+            "",
+            "Remote branch, default: the current branch",
+        ),
+        info(
+            "sync.interval_minutes",
+            "integer",
+            "0",
+            "Sync in the background every n minutes, 0 = only on demand",
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        let e = Settings::parse("colour = \"red\"\n").unwrap_err();
+        assert_eq!(e.line, Some(1), "{e}");
+        assert!(e.message.contains("unknown field"), "{e}");
+        let e = Settings::parse("[mail]\nfiltered_author = [\"me\"]\n").unwrap_err();
+        assert_eq!(e.line, Some(2), "{e}");
+        assert!(e.message.contains("unknown field"), "{e}");
+    }
+
+    #[test]
+    fn defaults() {
+        let (_, settings) = Settings::parse("").unwrap();
+        assert_eq!(settings, Settings::default());
+        assert!(settings.mail.filtered_authors.is_empty());
+        for info in schema() {
+            if info.key == "mail.filtered_authors" {
+                assert_eq!(info.default, "[]");
+            }
+        }
+    }
+
+    #[test]
+    fn lenient_parsing_keeps_the_valid_parts() {
+        let raw = "# mine\n[tags]\nx = \"red\"\n\n[git]\ncommit_window_seconds = 99999\n\n\
+                   [mail]\nfiltered_authors = [\"me\"]\n";
+        let (document, settings, error) = Settings::parse_lenient(raw);
+        assert_eq!(document.to_string(), raw);
+        assert_eq!(settings.tags.unwrap()["x"], "red");
+        assert_eq!(settings.mail.filtered_authors, ["me"]);
+        assert_eq!(settings.git, GitSettings::default());
+        let error = error.unwrap();
+        assert_eq!(error.key.as_deref(), Some("git.commit_window_seconds"));
+        assert_eq!(error.line, Some(6));
+
+        //not even toml: all defaults
+        let (document, settings, error) = Settings::parse_lenient("[git\n");
+        assert_eq!(document.to_string(), "");
+        assert_eq!(settings, Settings::default());
+        assert!(error.is_some());
+
+        let (_, _, error) = Settings::parse_lenient("[git]\ncommit_window_seconds = 5\n");
+        assert!(error.is_none());
+    }
+}
@@ -1,12 +1,18 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
+use crate::commit_queue::{combined_message, CommitQueue};
+use crate::merge;
 use crate::openai;
+use crate::query::Query;
+use crate::search::{SearchIndex, SearchQuery};
+use crate::settings::Settings;
 use anyhow::{anyhow, bail, Context, Result};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use once_cell::unsync::Lazy;
 use regex::Regex;
 use serde::{ser::Serializer, Deserialize, Serialize};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::{
     cmp::Ordering,
@@ -73,53 +79,111 @@ impl TreePath {
         }
     }
 
+    /// One path component the way to_human writes it: A-Z for 0..=25, digits above
+    pub fn component_to_human(block: u32) -> String {
+        if block < 26 {
+            ((b'A' + block as u8) as char).to_string()
+        } else {
+            block.to_string()
+        }
+    }
+
     pub fn to_human(self: &TreePath) -> String {
         let mut r = String::new();
         let mut last_was_number = false;
         for block in self.iter() {
-            if *block < 26 {
-                r.push((b'A' + *block as u8) as char);
-                last_was_number = false;
-            } else {
-                if last_was_number {
-                    r.push('/');
-                }
-                r.push_str(&block.to_string());
-                last_was_number = true;
+            let is_number = *block >= 26;
+            if is_number && last_was_number {
+                r.push('/');
             }
+            r.push_str(&TreePath::component_to_human(*block));
+            last_was_number = is_number;
         }
         r
     }
+
+    /// Parse a human path, as written by to_human.
+    ///
+    /// Grammar:
+    ///   path   := block*            ('' is the root)
+    ///   block  := letter | number
+    ///   letter := 'A'..'Z'          -> 0..=25
+    ///   number := digits >= 26, no leading zeros
+    ///   two numbers in a row are separated by a single '/' ('26/27'),
+    ///   '/' is not allowed anywhere else.
+    /// Surrounding whitespace is ignored, everything else is an error.
     pub fn from_human(path: &str) -> Result<TreePath> {
-        //each block is either
-        // one letter A-Z
-        // only digits
-        // if two digit blocks follow each other, they're seperated with /
-        // the letters get turned into 0..26
-        // the digits are numbers > 26.
-        //
-        //
-        let regex = Regex::new(r"([A-Z]|[0-9]+|/[0-9]+)/?").unwrap();
+        let input = path.trim();
+        let chars: Vec<char> = input.chars().collect();
         let mut res: Vec<u32> = Vec::new();
-
-        for cap in regex.captures_iter(path) {
-            let group = cap.get(1).unwrap().as_str();
-            match group.chars().next().unwrap() {
+        let mut last_was_number = false;
+        let mut ii = 0;
+        while ii < chars.len() {
+            let c = chars[ii];
+            match c {
                 'A'..='Z' => {
-                    let idx = group.chars().next().unwrap() as u32 - b'A' as u32;
-                    res.push(idx);
+                    res.push(c as u32 - 'A' as u32);
+                    last_was_number = false;
+                    ii += 1;
                 }
                 '/' => {
-                    let num = group[1..].parse::<u32>().unwrap();
-                    res.push(num);
+                    let next_is_digit = chars.get(ii + 1).map_or(false, |c| c.is_ascii_digit());
+                    if !(last_was_number && next_is_digit) {
+                        bail!(
+                            "Invalid path '{}': '/' at position {} - it only separates two numbers (26/27)",
+                            input,
+                            ii
+                        );
+                    }
+                    last_was_number = false;
+                    ii += 1;
                 }
-                _ => {
-                    let num = group.parse::<u32>().unwrap();
+                '0'..='9' => {
+                    let start = ii;
+                    while ii < chars.len() && chars[ii].is_ascii_digit() {
+                        ii += 1;
+                    }
+                    let digits: String = chars[start..ii].iter().collect();
+                    if digits.len() > 1 && digits.starts_with('0') {
+                        bail!(
+                            "Invalid path '{}': number '{}' at position {} has leading zeros",
+                            input,
+                            digits,
+                            start
+                        );
+                    }
+                    let num = digits.parse::<u32>().with_context(|| {
+                        format!(
+                            "Invalid path '{}': number '{}' at position {} is too large",
+                            input, digits, start
+                        )
+                    })?;
+                    if num < 26 {
+                        bail!(
+                            "Invalid path '{}': number {} at position {} - write it as the letter '{}'",
+                            input,
+                            num,
+                            start,
+                            TreePath::component_to_human(num)
+                        );
+                    }
                     res.push(num);
+                    last_was_number = true;
                 }
+                'a'..='z' => bail!(
+                    "Invalid path '{}': lowercase '{}' at position {} - path letters are A-Z",
+                    input,
+                    c,
+                    ii
+                ),
+                _ => bail!(
+                    "Invalid path '{}': unexpected '{}' at position {}",
+                    input,
+                    c,
+                    ii
+                ),
             }
         }
-
         Ok(TreePath::from(res))
     }
 
@@ -188,20 +252,200 @@ pub(crate) struct Header {
     pub has_more_content: bool,
 }
 
+/// Typed value of a node property (':key: value' lines at the top of node.adoc)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub(crate) enum PropertyValue {
+    Date(chrono::NaiveDate),
+    DateTime(chrono::NaiveDateTime),
+    Number(i64),
+    Text(String),
+}
+
+const PROPERTY_DATE_FORMAT: &'static str = "%Y-%m-%d";
+const PROPERTY_DATETIME_FORMAT: &'static str = "%Y-%m-%d %H:%M";
+
+impl PropertyValue {
+    /// Parse a value for key. Well known keys are strictly typed,
+    /// everything else is a date, datetime or number if it looks like one, text otherwise.
+    pub fn parse(key: &str, value: &str) -> Result<PropertyValue> {
+        let value = value.trim();
+        let as_date = || {
+            chrono::NaiveDate::parse_from_str(value, PROPERTY_DATE_FORMAT)
+                .ok()
+                .map(PropertyValue::Date)
+        };
+        let as_datetime = || {
+            chrono::NaiveDateTime::parse_from_str(value, PROPERTY_DATETIME_FORMAT)
+                .or_else(|_| chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+                .ok()
+                .map(PropertyValue::DateTime)
+        };
+        let as_number = || value.parse::<i64>().ok().map(PropertyValue::Number);
+        match key {
+            "created" | "modified" => as_datetime().or_else(as_date).with_context(|| {
+                format!("{key} must be a date (YYYY-MM-DD [HH:MM]), not '{value}'")
+            }),
+            "due" => {
+                as_date().with_context(|| format!("due must be a date (YYYY-MM-DD), not '{value}'"))
+            }
+            "priority" => {
+                as_number().with_context(|| format!("priority must be a number, not '{value}'"))
+            }
+            "status" => Ok(PropertyValue::Text(value.to_string())),
+            "alias" => {
+                if Node::parse_aliases(value).count() == value.split(',').count() {
+                    Ok(PropertyValue::Text(value.to_string()))
+                } else {
+                    bail!("alias must be names of letters, digits, - and _, separated by ',' - not '{value}'")
+                }
+            }
+            _ => Ok(as_datetime()
+                .or_else(as_date)
+                .or_else(as_number)
+                .unwrap_or_else(|| PropertyValue::Text(value.to_string()))),
+        }
+    }
+}
+
+impl core::fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyValue::Date(d) => write!(f, "{}", d.format(PROPERTY_DATE_FORMAT)),
+            PropertyValue::DateTime(d) => write!(f, "{}", d.format(PROPERTY_DATETIME_FORMAT)),
+            PropertyValue::Number(n) => write!(f, "{}", n),
+            PropertyValue::Text(t) => write!(f, "{}", t),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, Serialize)]
 pub(crate) struct Node {
     pub path: TreePath,
+    pub id: String,
     pub header: Header,
     pub raw: String,
+    //from the ':key: value' block at the top of raw
+    pub properties: BTreeMap<String, PropertyValue>,
+    //link targets (as written, human path or id:...) of all <<florg:...>> links in raw
+    pub links: Vec<String>,
+    //both sides, if raw contains git conflict markers
+    pub conflict: Option<Conflict>,
     //children: Vec<Node>,
 }
 
+/// The two versions of a node whose text has git conflict markers
+/// (<<<<<<< ours ======= theirs >>>>>>>) - e.g. after syncing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Conflict {
+    pub ours: String,
+    pub theirs: String,
+}
+
+/// What a node looked like when it was opened in the editor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditBase {
+    pub path: TreePath,
+    pub id: String,
+    //None for a new node (a placeholder)
+    pub raw: Option<String>,
+}
+
+/// Where the text from the editor ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditOutcome {
+    //node unchanged meanwhile (but maybe moved to path)
+    Saved(TreePath),
+    //node changed meanwhile, the changes were merged
+    Merged(TreePath),
+    //conflicting changes - the edit was stored as a new node next to it
+    SavedAsSibling {
+        original: TreePath,
+        sibling: TreePath,
+    },
+}
+
+/// An editor temp file left over from a crash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeftoverEdit {
+    //the temp file, relative to the data path
+    pub name: String,
+    //human path of the node it belongs to
+    pub path: String,
+    //the edited text (below the -- line)
+    pub content: String,
+    pub node_exists: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeftoverAction {
+    //overwrite the node with the temp file's content
+    Apply,
+    Discard,
+    //store it as a new sibling of the node
+    SaveAsNew,
+}
+
+impl LeftoverAction {
+    pub fn parse(action: &str) -> Result<LeftoverAction> {
+        Ok(match action {
+            "apply" => LeftoverAction::Apply,
+            "discard" => LeftoverAction::Discard,
+            "new" => LeftoverAction::SaveAsNew,
+            _ => bail!("Unknown action '{}' - apply, discard or new", action),
+        })
+    }
+}
+
+/// The node text from an editor temp file - everything after the '--' line
+pub fn parse_raw_content(raw_content: &str) -> &str {
+    match raw_content.split_once("\n--\n") {
+        Some((_header, content)) => content.trim(),
+        None => &raw_content,
+    }
+}
+
+/// How to resolve a Conflict: take one side, or replace the node text altogether
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    Ours,
+    Theirs,
+    Text(String),
+}
+
+impl ConflictResolution {
+    /// 'ours', 'theirs', or anything else as the new text
+    pub fn from_choice(choice_or_text: &str) -> ConflictResolution {
+        match choice_or_text {
+            "ours" => ConflictResolution::Ours,
+            "theirs" => ConflictResolution::Theirs,
+            text => ConflictResolution::Text(text.to_string()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Storage {
     pub data_path: PathBuf,
-    git_binary: String,
-    nodes: Vec<Node>,
-    pub settings: toml_edit::Document,
+    repo: Repository,
+    commits: CommitQueue,
+    nodes: BTreeMap<TreePath, Node>,
+    //node id -> current path
+    ids: HashMap<String, TreePath>,
+    //target node id -> ids of the nodes linking to it
+    backlinks: HashMap<String, HashSet<String>>,
+    //link target (as written) that resolves to no node (yet) -> ids of the nodes linking to it
+    unresolved_links: HashMap<String, HashSet<String>>,
+    //alias (lowercase) -> ids of the nodes declaring it in their ':alias:' property.
+    //More than one is a duplicate - the first node in path order gets the alias.
+    aliases: HashMap<String, HashSet<String>>,
+    //full text index over the node contents, keyed by node id
+    search_index: SearchIndex,
+    pub settings: Settings,
+    //settings.toml as written, so storing it keeps comments and formatting
+    settings_document: toml_edit::Document,
+    //why settings.toml could not be (fully) used
+    settings_error: Option<String>,
 
     pub(crate) chatgpt: Option<openai::ChatGPT>,
 }
@@ -211,6 +455,25 @@ pub struct MailAccount {
     pub addresses: Vec<String>,
 }
 
+/// An alias declared by several nodes (human paths, in path order)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateAlias {
+    pub alias: String,
+    pub paths: Vec<String>,
+}
+
+/// A deleted subtree in .trash/<name>/, the nodes live in .trash/<name>/node
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrashEntry {
+    #[serde(skip_deserializing)]
+    pub name: String,
+    pub original_path: String,
+    pub id: String,
+    pub title: String,
+    pub deleted: chrono::NaiveDateTime,
+    pub node_count: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct GitHistoryEntry {
     hash: String,
@@ -218,6 +481,616 @@ pub struct GitHistoryEntry {
     message: String,
 }
 
+/// What a commit did to one node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeDiff {
+    //where the node is after the commit (before, if it was deleted)
+    pub path: String,
+    //set if the node was moved
+    pub old_path: Option<String>,
+    pub title: String,
+    //added, modified, moved or deleted
+    pub status: String,
+    pub old_text: Option<String>,
+    pub new_text: Option<String>,
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffHunk {
+    //1 based, like the @@ lines of a unified diff
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+    //equal, insert or delete
+    pub tag: String,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitDiff {
+    pub hash: String,
+    pub date: String,
+    pub message: String,
+    pub nodes: Vec<NodeDiff>,
+}
+
+/// A commit that touched a node, path is where the node was at that time
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NodeHistoryEntry {
+    pub hash: String,
+    pub date: String,
+    pub message: String,
+    pub path: String,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+/// The git repository the data path lives in.
+/// That's usually data_path itself, but may be a parent directory.
+/// All paths going in and out are relative to the data path.
+pub(crate) struct Repository {
+    repo: git2::Repository,
+    //data_path relative to the work dir of the repository
+    prefix: PathBuf,
+}
+
+impl std::fmt::Debug for Repository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Repository({:?}, prefix {:?})",
+            self.repo.path(),
+            self.prefix
+        )
+    }
+}
+
+impl Repository {
+    /// Open the repository containing data_path
+    pub(crate) fn open(data_path: &Path) -> Result<Repository> {
+        let repo = git2::Repository::discover(data_path)
+            .with_context(|| format!("no git repository at or above {:?}", data_path))?;
+        Self::from_git2(repo, data_path)
+    }
+
+    /// Open the repository containing data_path, creating one in data_path if there is none
+    pub(crate) fn open_or_init(data_path: &Path) -> Result<Repository> {
+        let repo = match git2::Repository::discover(data_path) {
+            Ok(repo) => repo,
+            Err(_) => git2::Repository::init(data_path)
+                .with_context(|| format!("git init in {:?} failed", data_path))?,
+        };
+        Self::from_git2(repo, data_path)
+    }
+
+    fn from_git2(repo: git2::Repository, data_path: &Path) -> Result<Repository> {
+        let workdir = repo
+            .workdir()
+            .context("git repository is bare")?
+            .canonicalize()?;
+        let prefix = data_path
+            .canonicalize()?
+            .strip_prefix(&workdir)
+            .context("data path is outside the repository's work dir")?
+            .to_path_buf();
+        Ok(Repository { repo, prefix })
+    }
+
+    fn to_repo_path(&self, file: &Path) -> PathBuf {
+        self.prefix.join(file)
+    }
+
+    /// None for files outside the data path
+    fn from_repo_path(&self, file: &Path) -> Option<PathBuf> {
+        file.strip_prefix(&self.prefix)
+            .ok()
+            .map(|x| x.to_path_buf())
+    }
+
+    //everything below the data path
+    fn data_pathspec(&self) -> String {
+        if self.prefix.as_os_str().is_empty() {
+            "*".to_string()
+        } else {
+            self.prefix.to_string_lossy().to_string()
+        }
+    }
+
+    fn signature(&self) -> Result<git2::Signature<'static>> {
+        //fall back if user.name/user.email are not configured
+        Ok(self
+            .repo
+            .signature()
+            .or_else(|_| git2::Signature::now("florg", "florg@localhost"))?)
+    }
+
+    fn head_commit(&self) -> Result<Option<git2::Commit<'_>>> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?)),
+            Err(e)
+                if e.code() == git2::ErrorCode::UnbornBranch
+                    || e.code() == git2::ErrorCode::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e).context("failed to read HEAD"),
+        }
+    }
+
+    /// Stage everything below the data path (including deletions, respecting .gitignore)
+    /// and commit it. Returns None if there was nothing to commit.
+    pub(crate) fn add_all_and_commit(&self, msg: &str) -> Result<Option<git2::Oid>> {
+        let mut index = self.repo.index().context("failed to open git index")?;
+        let pathspec = self.data_pathspec();
+        index
+            .add_all([&pathspec], git2::IndexAddOption::DEFAULT, None)
+            .context("git add failed")?;
+        index
+            .update_all([&pathspec], None)
+            .context("git add (removals) failed")?;
+        index.write().context("failed to write git index")?;
+        let tree_id = index.write_tree().context("failed to write git tree")?;
+        let parent = self.head_commit()?;
+        let tree = self.repo.find_tree(tree_id)?;
+        match &parent {
+            Some(parent) if parent.tree_id() == tree_id => return Ok(None),
+            //no empty root commit - a sync would have to merge it with unrelated history
+            None if tree.is_empty() => return Ok(None),
+            _ => {}
+        }
+        let sig = self.signature()?;
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let oid = self
+            .repo
+            .commit(Some("HEAD"), &sig, &sig, msg, &tree, &parents)
+            .context("git commit failed")?;
+        Ok(Some(oid))
+    }
+
+    fn history_entry(commit: &git2::Commit) -> GitHistoryEntry {
+        use chrono::TimeZone;
+        let time = commit.time();
+        //in the committer's time zone
+        let date = chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+            .and_then(|tz| tz.timestamp_opt(time.seconds(), 0).single())
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        GitHistoryEntry {
+            hash: commit.id().to_string(),
+            date,
+            message: commit.message().unwrap_or("").trim().to_string(),
+        }
+    }
+
+    pub(crate) fn find_commit(&self, rev: &str) -> Result<git2::Commit<'_>> {
+        self.repo
+            .revparse_single(rev)
+            .and_then(|obj| obj.peel_to_commit())
+            .with_context(|| format!("unknown revision '{}'", rev))
+    }
+
+    /// The newest limit commits, newest first
+    pub(crate) fn history(&self, limit: usize) -> Result<Vec<GitHistoryEntry>> {
+        if self.head_commit()?.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut walk = self.repo.revwalk()?;
+        walk.push_head()?;
+        let mut res = Vec::new();
+        for oid in walk.take(limit) {
+            res.push(Self::history_entry(&self.repo.find_commit(oid?)?));
+        }
+        Ok(res)
+    }
+
+    /// Changes of commit compared to its first parent, with rename detection
+    fn diff_to_parent(&self, commit: &git2::Commit) -> Result<git2::Diff<'_>> {
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let mut diff =
+            self.repo
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+        Ok(diff)
+    }
+
+    /// (old file, new file) for every file commit changed - None where it did not
+    /// exist (or was outside the data path)
+    pub(crate) fn changed_files(
+        &self,
+        commit: &git2::Commit,
+    ) -> Result<Vec<(Option<PathBuf>, Option<PathBuf>)>> {
+        let diff = self.diff_to_parent(commit)?;
+        Ok(diff
+            .deltas()
+            .map(|delta| {
+                let old = match delta.status() {
+                    git2::Delta::Added => None,
+                    _ => delta.old_file().path(),
+                };
+                let new = match delta.status() {
+                    git2::Delta::Deleted => None,
+                    _ => delta.new_file().path(),
+                };
+                (
+                    old.and_then(|p| self.from_repo_path(p)),
+                    new.and_then(|p| self.from_repo_path(p)),
+                )
+            })
+            .filter(|change| change != &(None, None))
+            .collect())
+    }
+
+    /// The commits that changed file, newest first, following renames.
+    /// Each with the name the file had in that commit.
+    pub(crate) fn file_history(
+        &self,
+        file: &Path,
+        limit: usize,
+    ) -> Result<Vec<(GitHistoryEntry, PathBuf)>> {
+        let mut res = Vec::new();
+        if self.head_commit()?.is_none() {
+            return Ok(res);
+        }
+        let mut current = self.to_repo_path(file);
+        let mut walk = self.repo.revwalk()?;
+        walk.push_head()?;
+        walk.simplify_first_parent()?;
+        for oid in walk {
+            if res.len() >= limit {
+                break;
+            }
+            let commit = self.repo.find_commit(oid?)?;
+            let diff = self.diff_to_parent(&commit)?;
+            let delta = diff.deltas().find(|d| {
+                d.status() != git2::Delta::Deleted && d.new_file().path() == Some(&current)
+            });
+            let delta = match delta {
+                Some(d) => d,
+                None => continue,
+            };
+            if let Some(name) = self.from_repo_path(&current) {
+                res.push((Self::history_entry(&commit), name));
+            }
+            match delta.status() {
+                git2::Delta::Added => break,
+                git2::Delta::Renamed | git2::Delta::Copied => {
+                    current = delta
+                        .old_file()
+                        .path()
+                        .context("rename without old path")?
+                        .to_path_buf();
+                }
+                _ => {}
+            }
+        }
+        Ok(res)
+    }
+
+    /// Contents of file at commit, None if it did not exist
+    pub(crate) fn file_at(&self, commit: &git2::Commit, file: &Path) -> Result<Option<String>> {
+        let entry = match commit.tree()?.get_path(&self.to_repo_path(file)) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let blob = entry.to_object(&self.repo)?.peel_to_blob()?;
+        Ok(Some(
+            String::from_utf8(blob.content().to_vec()).context("file is not utf8")?,
+        ))
+    }
+
+    /// The commits after ancestor up to HEAD, newest first
+    pub(crate) fn commits_since(&self, ancestor: &git2::Commit) -> Result<Vec<git2::Commit<'_>>> {
+        let head = self.head_commit()?.context("no commits yet")?;
+        if !self.is_ancestor(ancestor.id(), head.id())? {
+            bail!("{} is not part of the current history", ancestor.id());
+        }
+        let mut walk = self.repo.revwalk()?;
+        walk.push(head.id())?;
+        walk.hide(ancestor.id())?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL)?;
+        let mut res = Vec::new();
+        for oid in walk {
+            res.push(self.repo.find_commit(oid?)?);
+        }
+        Ok(res)
+    }
+
+    /// is ancestor an ancestor of (or equal to) commit?
+    pub(crate) fn is_ancestor(&self, ancestor: git2::Oid, commit: git2::Oid) -> Result<bool> {
+        Ok(ancestor == commit || self.repo.graph_descendant_of(commit, ancestor)?)
+    }
+
+    /// Commit reverting commits (newest first) on top of HEAD as one commit.
+    /// Untracked files are left alone, and locally modified files the revert would
+    /// touch make it fail instead of being overwritten.
+    pub(crate) fn revert_commits(&self, commits: &[git2::Commit], msg: &str) -> Result<git2::Oid> {
+        let head = self.head_commit()?.context("nothing to revert")?;
+        let sig = self.signature()?;
+        //revert one after the other - the intermediate commits are never referenced
+        let mut current = head.clone();
+        for commit in commits {
+            let mainline = if commit.parent_count() > 1 { 1 } else { 0 };
+            let mut index = self
+                .repo
+                .revert_commit(commit, &current, mainline, None)
+                .with_context(|| format!("failed to revert {}", commit.id()))?;
+            if index.has_conflicts() {
+                bail!(
+                    "Can not revert '{}' ({}): it conflicts with later changes",
+                    commit.summary().unwrap_or(""),
+                    commit.id()
+                );
+            }
+            let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+            let oid = self
+                .repo
+                .commit(None, &sig, &sig, msg, &tree, &[&current])?;
+            current = self.repo.find_commit(oid)?;
+        }
+        let tree = current.tree()?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.safe();
+        self.repo
+            .checkout_tree(tree.as_object(), Some(&mut checkout))
+            .context("failed to update the files - are there uncommitted changes?")?;
+        let oid = self
+            .repo
+            .commit(Some("HEAD"), &sig, &sig, msg, &tree, &[&head])
+            .context("git commit failed")?;
+        Ok(oid)
+    }
+
+    /// name of the checked out branch
+    pub(crate) fn current_branch(&self) -> Result<String> {
+        let head = self.repo.find_reference("HEAD")?;
+        if let Some(target) = head.symbolic_target() {
+            //also works on a fresh repository without commits
+            if let Some(branch) = target.strip_prefix("refs/heads/") {
+                return Ok(branch.to_string());
+            }
+        }
+        let head = self.repo.head().context("no branch checked out")?;
+        if !head.is_branch() {
+            bail!("HEAD is detached - check out a branch to sync");
+        }
+        Ok(head
+            .shorthand()
+            .context("branch name not utf8")?
+            .to_string())
+    }
+
+    /// make sure remote exists (and points to url, if given)
+    pub(crate) fn ensure_remote(&self, remote: &str, url: Option<&str>) -> Result<()> {
+        match (self.repo.find_remote(remote), url) {
+            (Ok(existing), Some(url)) if existing.url() != Some(url) => {
+                self.repo.remote_set_url(remote, url)?
+            }
+            (Ok(_), _) => {}
+            (Err(_), Some(url)) => {
+                self.repo
+                    .remote(remote, url)
+                    .with_context(|| format!("failed to add remote {} ({})", remote, url))?;
+            }
+            (Err(_), None) => bail!("no git remote '{}' and no url configured", remote),
+        }
+        Ok(())
+    }
+
+    fn remote_callbacks() -> git2::RemoteCallbacks<'static> {
+        let mut callbacks = git2::RemoteCallbacks::new();
+        callbacks.credentials(|url, username, allowed| {
+            if allowed.contains(git2::CredentialType::SSH_KEY) {
+                git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+                git2::Cred::credential_helper(&git2::Config::open_default()?, url, username)
+            } else {
+                git2::Cred::default()
+            }
+        });
+        callbacks
+    }
+
+    fn remote_tracking_ref(remote: &str, remote_branch: &str) -> String {
+        format!("refs/remotes/{}/{}", remote, remote_branch)
+    }
+
+    pub(crate) fn fetch(&self, remote: &str, remote_branch: &str) -> Result<()> {
+        let mut options = git2::FetchOptions::new();
+        options.remote_callbacks(Self::remote_callbacks());
+        self.repo
+            .find_remote(remote)?
+            .fetch(
+                &[format!(
+                    "+refs/heads/{}:{}",
+                    remote_branch,
+                    Self::remote_tracking_ref(remote, remote_branch)
+                )],
+                Some(&mut options),
+                None,
+            )
+            .with_context(|| format!("git fetch from {} failed", remote))
+    }
+
+    fn fetched_commit(
+        &self,
+        remote: &str,
+        remote_branch: &str,
+    ) -> Result<Option<git2::Commit<'_>>> {
+        match self
+            .repo
+            .find_reference(&Self::remote_tracking_ref(remote, remote_branch))
+        {
+            Ok(reference) => Ok(Some(reference.peel_to_commit()?)),
+            //nothing pushed there yet
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// (commits only we have, commits only the remote has), as of the last fetch
+    pub(crate) fn ahead_behind(&self, remote: &str, remote_branch: &str) -> Result<(usize, usize)> {
+        let head = match self.head_commit()? {
+            Some(head) => head,
+            None => {
+                let behind = match self.fetched_commit(remote, remote_branch)? {
+                    Some(theirs) => {
+                        let mut walk = self.repo.revwalk()?;
+                        walk.push(theirs.id())?;
+                        walk.count()
+                    }
+                    None => 0,
+                };
+                return Ok((0, behind));
+            }
+        };
+        match self.fetched_commit(remote, remote_branch)? {
+            Some(theirs) => Ok(self.repo.graph_ahead_behind(head.id(), theirs.id())?),
+            None => {
+                let mut walk = self.repo.revwalk()?;
+                walk.push(head.id())?;
+                Ok((walk.count(), 0))
+            }
+        }
+    }
+
+    /// Bring the fetched commits into the current branch - fast forward if possible,
+    /// merge commit otherwise. Conflicting files are committed with conflict markers;
+    /// they are returned (relative to the data path).
+    pub(crate) fn merge_fetched(&self, remote: &str, remote_branch: &str) -> Result<Vec<PathBuf>> {
+        let theirs = match self.fetched_commit(remote, remote_branch)? {
+            Some(theirs) => theirs,
+            None => return Ok(Vec::new()),
+        };
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.safe();
+        let ours = match self.head_commit()? {
+            Some(ours) => ours,
+            None => {
+                //nothing committed here yet - just take theirs
+                self.repo
+                    .checkout_tree(theirs.as_object(), Some(&mut checkout))
+                    .context("failed to update the files - are there uncommitted changes?")?;
+                let head = self.repo.find_reference("HEAD")?;
+                let branch = head
+                    .symbolic_target()
+                    .unwrap_or("refs/heads/main")
+                    .to_string();
+                self.repo
+                    .reference(&branch, theirs.id(), true, "florg sync: initial pull")?;
+                return Ok(Vec::new());
+            }
+        };
+        if self.is_ancestor(theirs.id(), ours.id())? {
+            return Ok(Vec::new());
+        }
+        if self.is_ancestor(ours.id(), theirs.id())? {
+            self.repo
+                .checkout_tree(theirs.as_object(), Some(&mut checkout))
+                .context("failed to update the files - are there uncommitted changes?")?;
+            self.repo
+                .head()?
+                .set_target(theirs.id(), "florg sync: fast-forward")?;
+            return Ok(Vec::new());
+        }
+
+        let mut merged = self.repo.merge_commits(&ours, &theirs, None)?;
+        let mut conflicts = Vec::new();
+        let tree = if merged.has_conflicts() {
+            for conflict in merged.conflicts()? {
+                let conflict = conflict?;
+                let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+                if let Some(entry) = entry {
+                    let path = PathBuf::from(String::from_utf8_lossy(&entry.path).to_string());
+                    conflicts.extend(self.from_repo_path(&path));
+                }
+            }
+            //write the files with <<<<<<< markers, then commit them as they are
+            checkout.allow_conflicts(true).conflict_style_merge(true);
+            self.repo
+                .checkout_index(Some(&mut merged), Some(&mut checkout))
+                .context("failed to write the merge result")?;
+            let mut index = self.repo.index()?;
+            index.add_all([self.data_pathspec()], git2::IndexAddOption::DEFAULT, None)?;
+            index.update_all([self.data_pathspec()], None)?;
+            index.write()?;
+            self.repo.find_tree(index.write_tree()?)?
+        } else {
+            let tree = self.repo.find_tree(merged.write_tree_to(&self.repo)?)?;
+            self.repo
+                .checkout_tree(tree.as_object(), Some(&mut checkout))
+                .context("failed to update the files - are there uncommitted changes?")?;
+            tree
+        };
+        let sig = self.signature()?;
+        let msg = if conflicts.is_empty() {
+            format!("Merged changes from {}/{}", remote, remote_branch)
+        } else {
+            format!(
+                "Merged changes from {}/{} (with conflicts)",
+                remote, remote_branch
+            )
+        };
+        self.repo
+            .commit(Some("HEAD"), &sig, &sig, &msg, &tree, &[&ours, &theirs])
+            .context("failed to commit the merge")?;
+        Ok(conflicts)
+    }
+
+    pub(crate) fn push(&self, remote: &str, branch: &str, remote_branch: &str) -> Result<()> {
+        //the branch may move on while we push - only claim what it was before
+        let pushed = self
+            .repo
+            .refname_to_id(&format!("refs/heads/{}", branch))
+            .context("nothing to push")?;
+        let rejected = std::cell::RefCell::new(None);
+        {
+            let mut callbacks = Self::remote_callbacks();
+            callbacks.push_update_reference(|_, status| {
+                if let Some(status) = status {
+                    *rejected.borrow_mut() = Some(status.to_string());
+                }
+                Ok(())
+            });
+            let mut options = git2::PushOptions::new();
+            options.remote_callbacks(callbacks);
+            self.repo
+                .find_remote(remote)?
+                .push(
+                    &[format!(
+                        "refs/heads/{}:refs/heads/{}",
+                        branch, remote_branch
+                    )],
+                    Some(&mut options),
+                )
+                .with_context(|| format!("git push to {} failed", remote))?;
+        }
+        if let Some(reason) = rejected.into_inner() {
+            bail!("push to {} rejected: {}", remote, reason);
+        }
+        //so ahead_behind is right without another fetch
+        self.repo.reference(
+            &Self::remote_tracking_ref(remote, remote_branch),
+            pushed,
+            true,
+            "florg sync: pushed",
+        )?;
+        Ok(())
+    }
+}
+
 impl core::fmt::Display for TreePath {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -234,65 +1107,146 @@ impl core::fmt::Display for TreePath {
 
 pub const FLORG_FILENAME: &'static str = "node.adoc";
 pub const FLORG_CACHE_FILENAME: &'static str = "node.cache";
+pub const FLORG_ID_FILENAME: &'static str = "node.id";
+//editor temp files - not in the node folders, those move with move/swap
+pub const FLORG_EDIT_TEMP_DIR: &'static str = "temp";
+pub const NODE_ID_PREFIX: &'static str = "id:";
+//'@weekly-review' refers to the node with ':alias: weekly-review'
+pub const NODE_ALIAS_PREFIX: &'static str = "@";
+//message prefix of the commits made by git_undo
+const UNDO_COMMIT_PREFIX: &'static str = "Undo to state of ";
+//ignored in the generated .gitignore - deleted nodes stay local, git history has them anyway
+pub const FLORG_TRASH_DIR: &'static str = ".trash";
+const FLORG_TRASH_META_FILENAME: &'static str = "trash.json";
+
+//':key: value' lines at the very top of a node make up its properties
+static PROPERTY_REGEX: once_cell::sync::Lazy<Regex> =
+    once_cell::sync::Lazy::new(|| Regex::new(r"^:([A-Za-z0-9_-]+):(.*)$").unwrap());
+
+//links to other nodes: <<florg:BCD>>, <<florg:id:1a2b3c4d>> or <<florg:BCD,some label>>
+static LINK_REGEX: once_cell::sync::Lazy<Regex> =
+    once_cell::sync::Lazy::new(|| Regex::new(r"<<florg:([^,>]+)(,[^>]*)?>>").unwrap());
 pub const FLORG_SUFFIX: &'static str = ".adoc";
 
 impl Storage {
-    fn get_chatgtp_key(settings: &toml_edit::Document) -> Option<String> {
-        settings
-            .get("chatgpt")?
-            .get("api_key")?
-            .as_str()
-            .map(|x| x.to_string())
-    }
-    pub(crate) fn new(data_path: PathBuf, git_binary: String) -> Storage {
-        let settings =
-            Self::load_settings(&data_path, None).unwrap_or_else(|_| toml_edit::Document::new());
+    pub(crate) fn new(data_path: PathBuf) -> Result<Storage> {
+        let repo = Repository::open(&data_path)?;
+        let (settings_document, settings, settings_error) =
+            Self::load_settings_or_default(&data_path);
         //todo: make this robust
-        let chatgpt = Storage::get_chatgtp_key(&settings)
+        let chatgpt = settings
+            .chatgpt
+            .api_key
+            .as_ref()
             .map(|s| openai::ChatGPT::new(s.to_string(), data_path.clone()));
 
+        let commits = CommitQueue::new(settings.git.commit_window());
         let mut s = Storage {
             data_path,
-            nodes: Vec::new(),
-            git_binary,
+            nodes: BTreeMap::new(),
+            ids: HashMap::new(),
+            backlinks: HashMap::new(),
+            unresolved_links: HashMap::new(),
+            aliases: HashMap::new(),
+            search_index: SearchIndex::new(),
+            repo,
+            commits,
             settings,
+            settings_document,
+            settings_error,
             chatgpt,
         };
         s.reload();
-        s
+        Ok(s)
     }
 
     pub fn reload(&mut self) {
         let nodes = Self::parse_path(&self.data_path);
-        self.nodes = nodes;
+        self.nodes = nodes.into_iter().map(|n| (n.path.clone(), n)).collect();
+        self.assign_ids();
+        //'@alias' links need the aliases
+        self.rebuild_aliases();
+        self.rebuild_backlinks();
+        self.rebuild_search_index();
         //print a sorted list of the nodes path...
         /* let mut paths: Vec<_> = self.nodes.iter().map(|n| n.path.clone()).collect();
         paths.sort();
         dbg!(paths); */
-        let settings = Self::load_settings(&self.data_path, None)
-            .unwrap_or_else(|_| toml_edit::Document::new());
+        let (settings_document, settings, settings_error) =
+            Self::load_settings_or_default(&self.data_path);
+        self.settings_error = settings_error;
+        self.apply_settings(settings_document, settings);
+    }
 
-        let chatgpt = Storage::get_chatgtp_key(&settings)
+    fn apply_settings(&mut self, settings_document: toml_edit::Document, settings: Settings) {
+        self.chatgpt = settings
+            .chatgpt
+            .api_key
+            .as_ref()
             .map(|s| openai::ChatGPT::new(s.to_string(), self.data_path.clone()));
+        self.commits.set_window(settings.git.commit_window());
         self.settings = settings;
-        self.chatgpt = chatgpt;
+        self.settings_document = settings_document;
     }
 
     pub fn settings_filename(data_path: &PathBuf) -> PathBuf {
         data_path.join("settings.toml")
     }
 
-    pub fn load_settings(data_path: &PathBuf, raw: Option<String>) -> Result<toml_edit::Document> {
-        let raw = match raw {
-            Some(raw) => raw,
-            None => std::fs::read_to_string(Self::settings_filename(data_path))?,
-        };
-        let parsed = raw.parse::<toml_edit::Document>()?;
-        Ok(parsed)
+    /// read and validate settings.toml - a missing file means all defaults,
+    /// invalid settings are replaced by the defaults, see Settings::parse_lenient.
+    /// The error is kept for settings_error
+    fn load_settings_or_default(
+        data_path: &PathBuf,
+    ) -> (toml_edit::Document, Settings, Option<String>) {
+        let filename = Self::settings_filename(data_path);
+        if !filename.exists() {
+            return (toml_edit::Document::new(), Settings::default(), None);
+        }
+        match std::fs::read_to_string(&filename) {
+            Ok(raw) => {
+                let (document, settings, error) = Settings::parse_lenient(&raw);
+                let error = error.map(|e| e.to_string());
+                if let Some(e) = &error {
+                    println!("ignoring invalid settings: {}", e);
+                }
+                (document, settings, error)
+            }
+            Err(e) => (
+                toml_edit::Document::new(),
+                Settings::default(),
+                Some(format!("could not read {}: {}", filename.display(), e)),
+            ),
+        }
+    }
+
+    /// what was wrong with settings.toml when it was last (re)loaded
+    pub fn settings_error(&self) -> Option<&str> {
+        self.settings_error.as_deref()
+    }
+
+    /// settings.toml as stored
+    pub fn settings_raw(&self) -> String {
+        self.settings_document.to_string()
+    }
+
+    /// validate, apply and store new settings.toml contents (from the settings editor)
+    pub fn set_settings_raw(&mut self, raw: &str) -> Result<()> {
+        let (settings_document, settings) = Settings::parse(raw)?;
+        self.settings_error = None;
+        self.apply_settings(settings_document, settings);
+        self.store_settings()
+    }
+
+    /// change the settings document in place - e.g. from the UI - validate and store it
+    pub fn update_settings(&mut self, f: impl FnOnce(&mut toml_edit::Document)) -> Result<()> {
+        let mut document = self.settings_document.clone();
+        f(&mut document);
+        self.set_settings_raw(&document.to_string())
     }
 
     pub fn store_settings(&self) -> Result<()> {
-        let out = self.settings.to_string();
+        let out = self.settings_document.to_string();
         std::fs::write(self.data_path.join("settings.toml"), out).context("saving settings failed")
     }
 
@@ -305,6 +1259,10 @@ impl Storage {
         for entry in WalkDir::new(data_path)
             .into_iter()
             .filter_entry(|entry| {
+                //skip .git, .trash...
+                if entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.') {
+                    return false;
+                }
                 entry.file_type().is_dir()
                     || entry
                         .file_name()
@@ -324,7 +1282,7 @@ impl Storage {
                     .unwrap();
                 let tree_path = TreePath::from_path(path);
                 match tree_path {
-                    Ok(tree_path) => nodes.push(Node::parse(tree_path, entry.path())),
+                    Ok(tree_path) => nodes.push(Node::parse_or_empty(tree_path, entry.path())),
                     Err(_) => continue,
                 }
             }
@@ -337,16 +1295,7 @@ impl Storage {
                 let parent = p.parent();
                 if !paths.contains(&parent) {
                     let path = parent;
-                    let node = Node {
-                        path: path.clone(),
-                        header: Header {
-                            title: "(empty node)".to_string(),
-                            first_paragraph: "".to_string(),
-                            has_more_content: false,
-                        },
-                        raw: "".to_string(),
-                    };
-                    nodes.push(node);
+                    nodes.push(Node::empty(path.clone()));
                     paths.insert(path.clone());
                 }
             }
@@ -355,31 +1304,578 @@ impl Storage {
         nodes
     }
 
-    pub(crate) fn get_node(&self, path: &TreePath) -> Option<&Node> {
-        //todo: replace with binary search
-        self.nodes.iter().filter(|n| &n.path == path).next()
-    }
-
-    pub(crate) fn get_node_mut(&mut self, path: &TreePath) -> Option<&mut Node> {
-        self.nodes.iter_mut().filter(|n| &n.path == path).next()
+    /// Re-read the nodes at and below path from disk,
+    /// e.g. after a git pull or an edit outside of florg.
+    /// Returns the paths of the nodes that were added, changed or removed.
+    pub(crate) fn reload_subtree(&mut self, path: &TreePath) -> Vec<TreePath> {
+        let dir = Node::dirname_from_path(&self.data_path, path);
+        let on_disk: Vec<Node> = if dir.exists() {
+            Self::parse_path(&dir)
+                .into_iter()
+                .map(|mut node| {
+                    node.path = path.concat(&node.path);
+                    node
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.apply_reloaded(path, on_disk, true)
     }
 
-    pub(crate) fn delete_node(&mut self, path: &TreePath, commit: bool) -> Result<()> {
-        let node = self.get_node(path).context("node not found")?;
-        let file_path = node.dirname(&self.data_path);
-        std::fs::remove_dir_all(file_path).context("failed to remove_dir_all")?;
-        if commit {
-            self.add_and_commit(&format!("Deleted node {path} and children"))?;
-        }
-        self.nodes.retain(|n| !n.path.starts_with(path));
-        Ok(())
+    /// Re-read just the node at path (not its children) from disk.
+    pub(crate) fn reload_node(&mut self, path: &TreePath) -> Vec<TreePath> {
+        let filename = Node::dirname_from_path(&self.data_path, path).join(FLORG_FILENAME);
+        let on_disk = if filename.exists() {
+            vec![Node::parse_or_empty(path.clone(), &filename)]
+        } else if !self.children_paths_for(path).is_empty() {
+            vec![Node::empty(path.clone())]
+        } else {
+            Vec::new()
+        };
+        self.apply_reloaded(path, on_disk, false)
     }
 
-    pub(crate) fn move_node(
+    fn apply_reloaded(
         &mut self,
-        org_path: &TreePath,
-        new_path: &TreePath,
-        commit: bool,
+        path: &TreePath,
+        on_disk: Vec<Node>,
+        recursive: bool,
+    ) -> Vec<TreePath> {
+        let in_scope = |p: &TreePath| {
+            if recursive {
+                p.starts_with(path)
+            } else {
+                p == path
+            }
+        };
+        let disk_paths: HashSet<TreePath> = on_disk.iter().map(|n| n.path.clone()).collect();
+        let mut changed: Vec<TreePath> = self
+            .paths_below(path)
+            .into_iter()
+            .filter(|p| in_scope(p) && !disk_paths.contains(p))
+            .collect();
+        let mut updated = Vec::new();
+        for node in on_disk {
+            let unchanged = match self.get_node(&node.path) {
+                Some(existing) => {
+                    existing.raw.trim() == node.raw.trim()
+                        && (node.id.is_empty() || node.id == existing.id)
+                }
+                None => false,
+            };
+            if !unchanged {
+                changed.push(node.path.clone());
+                updated.push(node);
+            }
+        }
+        if changed.is_empty() {
+            return changed;
+        }
+        //only the changed nodes are (re)indexed
+        let mut old_ids = Vec::new();
+        for p in changed.iter() {
+            if let Some(old) = self.nodes.remove(p) {
+                if self.ids.get(&old.id) == Some(p) {
+                    self.ids.remove(&old.id);
+                }
+                self.unindex_links(&old.id);
+                self.search_index.remove(&old.id);
+                old_ids.push(old.id);
+            }
+        }
+        let mut new_links = Vec::new();
+        for mut node in updated {
+            Self::claim_id(&self.ids, &self.data_path, &mut node);
+            self.ids.insert(node.id.clone(), node.path.clone());
+            self.search_index.insert(&node.id, &node.raw);
+            let (id, aliases) = (node.id.clone(), node.aliases());
+            new_links.push((id.clone(), node.links.clone()));
+            self.nodes.insert(node.path.clone(), node);
+            self.update_aliases(&id, aliases);
+        }
+        let vanished: Vec<String> = old_ids
+            .into_iter()
+            .filter(|id| !self.ids.contains_key(id))
+            .collect();
+        self.forget_nodes(&vanished);
+        //all aliases are in place by now
+        for (id, links) in new_links {
+            self.index_links(&id, &links);
+        }
+        self.resolve_pending_links();
+        changed.sort();
+        changed
+    }
+
+    /// make sure every node has a unique id, and write out the ones we had to (re)assign.
+    /// Duplicates happen when somebody copies a node folder by hand -
+    /// the first node (in path order) keeps the id.
+    fn assign_ids(&mut self) {
+        self.ids.clear();
+        for node in self.nodes.values_mut() {
+            Self::claim_id(&self.ids, &self.data_path, node);
+            self.ids.insert(node.id.clone(), node.path.clone());
+        }
+    }
+
+    /// Make sure node has an id no other node holds - a new one (written out) if need be.
+    /// An id held by a node whose folder is gone (moved on disk, not reloaded yet) is free.
+    /// Conflict markers in node.id are resolved to our id.
+    fn claim_id(ids: &HashMap<String, TreePath>, data_path: &PathBuf, node: &mut Node) {
+        if node.id.is_empty() {
+            //empty nodes (no node.adoc) are not parsed, but may well have an id on disk
+            node.id = std::fs::read_to_string(node.dirname(data_path).join(FLORG_ID_FILENAME))
+                .map(|x| x.trim().to_string())
+                .unwrap_or_default();
+        }
+        //the same folder created on two machines - a merge leaves both ids in node.id
+        let mut write = match Node::extract_conflict(&node.id) {
+            Some(conflict) => {
+                let ours = conflict.ours.trim();
+                node.id = if ours.is_empty() {
+                    conflict.theirs.trim().to_string()
+                } else {
+                    ours.to_string()
+                };
+                true
+            }
+            None => false,
+        };
+        let taken = match ids.get(&node.id) {
+            Some(holder) => {
+                holder != &node.path && Node::dirname_from_path(data_path, holder).exists()
+            }
+            None => false,
+        };
+        if node.id.is_empty() || taken {
+            node.id = Self::new_node_id(ids, &node.path);
+            write = true;
+        }
+        if write {
+            if let Err(e) = node.write_id(data_path) {
+                println!("failed to write node id for {}: {:?}", node.path, e);
+            }
+        }
+    }
+
+    fn new_node_id(ids: &HashMap<String, TreePath>, path: &TreePath) -> String {
+        let mut counter = 0u32;
+        loop {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos())
+                .unwrap_or(0);
+            let hash = sha256::digest(format!("{nanos}-{counter}-{path}").as_bytes()).to_string();
+            let id = hash[..8].to_string();
+            if !ids.contains_key(&id) {
+                return id;
+            }
+            counter += 1;
+        }
+    }
+
+    fn rebuild_backlinks(&mut self) {
+        self.backlinks.clear();
+        self.unresolved_links.clear();
+        let sources: Vec<(String, Vec<String>)> = self
+            .nodes
+            .values()
+            .filter(|n| !n.links.is_empty())
+            .map(|n| (n.id.clone(), n.links.clone()))
+            .collect();
+        for (source_id, links) in sources {
+            self.index_links(&source_id, &links);
+        }
+    }
+
+    fn rebuild_search_index(&mut self) {
+        self.search_index.clear();
+        for node in self.nodes.values() {
+            self.search_index.insert(&node.id, &node.raw);
+        }
+    }
+
+    fn index_links(&mut self, source_id: &str, links: &[String]) {
+        for target in links {
+            let target_id = self
+                .resolve_path(target)
+                .ok()
+                .and_then(|p| self.get_node(&p))
+                .map(|n| n.id.clone());
+            match target_id {
+                Some(target_id) => self.backlinks.entry(target_id),
+                None => self.unresolved_links.entry(target.clone()),
+            }
+            .or_default()
+            .insert(source_id.to_string());
+        }
+    }
+
+    fn unindex_links(&mut self, source_id: &str) {
+        for index in [&mut self.backlinks, &mut self.unresolved_links] {
+            index.retain(|_, sources| {
+                sources.remove(source_id);
+                !sources.is_empty()
+            });
+        }
+    }
+
+    /// Index the links whose target didn't exist when they were indexed,
+    /// if it does now - call after nodes were added or moved
+    fn resolve_pending_links(&mut self) {
+        let resolved: Vec<(String, String)> = self
+            .unresolved_links
+            .keys()
+            .filter_map(|target| {
+                let path = self.resolve_path(target).ok()?;
+                Some((target.clone(), self.get_node(&path)?.id.clone()))
+            })
+            .collect();
+        for (target, target_id) in resolved {
+            if let Some(sources) = self.unresolved_links.remove(&target) {
+                self.backlinks.entry(target_id).or_default().extend(sources);
+            }
+        }
+    }
+
+    /// Drop removed nodes from the indices. Links pointing to them become
+    /// unresolved again, so they come back with the nodes (restore from trash).
+    fn forget_nodes(&mut self, removed_ids: &[String]) {
+        let mut orphaned_sources: HashSet<String> = HashSet::new();
+        for id in removed_ids {
+            self.update_aliases(id, Vec::new());
+            self.ids.remove(id);
+            orphaned_sources.extend(self.backlinks.remove(id).unwrap_or_default());
+            self.unindex_links(id);
+            self.search_index.remove(id);
+        }
+        for source_id in orphaned_sources {
+            if let Some(links) = self.get_node_by_id(&source_id).map(|n| n.links.clone()) {
+                self.unindex_links(&source_id);
+                self.index_links(&source_id, &links);
+            }
+        }
+    }
+
+    /// All nodes linking to the node at path, sorted by path
+    pub(crate) fn get_backlinks(&self, path: &TreePath) -> Vec<&Node> {
+        let mut res: Vec<&Node> = self
+            .get_node(path)
+            .and_then(|node| self.backlinks.get(&node.id))
+            .map(|sources| {
+                sources
+                    .iter()
+                    .filter_map(|id| self.get_node_by_id(id))
+                    .collect()
+            })
+            .unwrap_or_default();
+        res.sort();
+        res
+    }
+
+    pub(crate) fn get_node_by_id(&self, id: &str) -> Option<&Node> {
+        self.ids.get(id).and_then(|path| self.get_node(path))
+    }
+
+    /// Turn user/js input into a TreePath.
+    /// Accepts a human path ('BCD'), a node id ('id:1a2b3c4d') or an alias ('@weekly-review')
+    pub(crate) fn resolve_path(&self, input: &str) -> Result<TreePath> {
+        if let Some(id) = input.strip_prefix(NODE_ID_PREFIX) {
+            return self
+                .ids
+                .get(id.trim())
+                .cloned()
+                .with_context(|| format!("no node with id '{}'", id));
+        }
+        if let Some(alias) = input.trim().strip_prefix(NODE_ALIAS_PREFIX) {
+            return self
+                .alias_target(&alias.to_lowercase())
+                .map(|node| node.path.clone())
+                .with_context(|| format!("no node with alias '{}'", alias));
+        }
+        TreePath::from_human(input)
+    }
+
+    /// Rebuild the alias map from the nodes' ':alias:' properties
+    fn rebuild_aliases(&mut self) {
+        self.aliases.clear();
+        for node in self.nodes.values() {
+            for alias in node.aliases() {
+                self.aliases
+                    .entry(alias)
+                    .or_default()
+                    .insert(node.id.clone());
+            }
+        }
+        for dup in self.duplicate_aliases() {
+            println!(
+                "alias '{}' is declared by several nodes: {}",
+                dup.alias,
+                dup.paths.join(", ")
+            );
+        }
+    }
+
+    /// Set the aliases node id declares (none: the node is gone).
+    /// Links using an alias that now points to another node are re-indexed -
+    /// those that point nowhere yet are left to resolve_pending_links.
+    fn update_aliases(&mut self, id: &str, declared: Vec<String>) {
+        let mut affected: HashSet<String> = declared.iter().cloned().collect();
+        affected.extend(
+            self.aliases
+                .iter()
+                .filter(|(_, ids)| ids.contains(id))
+                .map(|(alias, _)| alias.clone()),
+        );
+        let before: Vec<(String, Option<String>)> = affected
+            .into_iter()
+            .map(|alias| {
+                let target = self.alias_target(&alias).map(|n| n.id.clone());
+                (alias, target)
+            })
+            .collect();
+
+        self.aliases.retain(|_, ids| {
+            ids.remove(id);
+            !ids.is_empty()
+        });
+        for alias in declared {
+            self.aliases
+                .entry(alias)
+                .or_default()
+                .insert(id.to_string());
+        }
+
+        let mut relink: HashSet<String> = HashSet::new();
+        for (alias, old_target) in before {
+            let old_target = match old_target {
+                Some(old_target) => old_target,
+                None => continue,
+            };
+            if self.alias_target(&alias).map(|n| &n.id) == Some(&old_target) {
+                continue;
+            }
+            let link = format!("{}{}", NODE_ALIAS_PREFIX, alias);
+            relink.extend(
+                self.backlinks
+                    .get(&old_target)
+                    .into_iter()
+                    .flatten()
+                    .filter(|source| {
+                        self.get_node_by_id(source)
+                            .map(|n| n.links.iter().any(|l| l.trim().to_lowercase() == link))
+                            .unwrap_or(false)
+                    })
+                    .cloned(),
+            );
+        }
+        for source_id in relink {
+            if let Some(links) = self.get_node_by_id(&source_id).map(|n| n.links.clone()) {
+                self.unindex_links(&source_id);
+                self.index_links(&source_id, &links);
+            }
+        }
+    }
+
+    /// The node an alias (lowercase) refers to: the first one in path order declaring it
+    fn alias_target(&self, alias: &str) -> Option<&Node> {
+        self.aliases
+            .get(alias)?
+            .iter()
+            .filter_map(|id| self.get_node_by_id(id))
+            .min_by(|a, b| a.path.cmp(&b.path))
+    }
+
+    /// alias -> human path
+    pub(crate) fn aliases(&self) -> BTreeMap<String, String> {
+        self.aliases
+            .keys()
+            .filter_map(|alias| Some((alias.clone(), self.alias_target(alias)?.path.to_human())))
+            .collect()
+    }
+
+    /// Aliases declared by more than one node, sorted by alias
+    pub(crate) fn duplicate_aliases(&self) -> Vec<DuplicateAlias> {
+        let mut res: Vec<DuplicateAlias> = self
+            .aliases
+            .iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|(alias, ids)| {
+                let mut paths: Vec<&TreePath> =
+                    ids.iter().filter_map(|id| self.ids.get(id)).collect();
+                paths.sort();
+                DuplicateAlias {
+                    alias: alias.clone(),
+                    paths: paths.iter().map(|p| p.to_human()).collect(),
+                }
+            })
+            .collect();
+        res.sort_by(|a, b| a.alias.cmp(&b.alias));
+        res
+    }
+
+    pub(crate) fn get_node(&self, path: &TreePath) -> Option<&Node> {
+        self.nodes.get(path)
+    }
+
+    pub(crate) fn get_node_mut(&mut self, path: &TreePath) -> Option<&mut Node> {
+        self.nodes.get_mut(path)
+    }
+
+    /// path and all paths below it that have a node.
+    /// A subtree is a contiguous range in the (sorted) node map.
+    fn paths_below(&self, path: &TreePath) -> Vec<TreePath> {
+        self.nodes
+            .range(path.clone()..)
+            .take_while(|(p, _)| p.starts_with(path))
+            .map(|(p, _)| p.clone())
+            .collect()
+    }
+
+    /// Delete a node and its children by moving them into the trash
+    pub(crate) fn delete_node(&mut self, path: &TreePath, commit: bool) -> Result<()> {
+        let node = self.get_node(path).context("node not found")?;
+        let file_path = node.dirname(&self.data_path);
+        let deleted = chrono::Local::now().naive_local();
+        let mut entry = TrashEntry {
+            name: format!("{}-{}", deleted.format("%Y%m%d-%H%M%S"), node.id),
+            original_path: path.to_human(),
+            id: node.id.clone(),
+            title: node.header.title.clone(),
+            deleted,
+            node_count: self.paths_below(path).len(),
+        };
+        let trash_dir = self.data_path.join(FLORG_TRASH_DIR);
+        let mut counter = 1;
+        while trash_dir.join(&entry.name).exists() {
+            entry.name = format!(
+                "{}-{}-{}",
+                deleted.format("%Y%m%d-%H%M%S"),
+                node.id,
+                counter
+            );
+            counter += 1;
+        }
+        let entry_dir = trash_dir.join(&entry.name);
+        std::fs::create_dir_all(&entry_dir).context("failed to create trash entry")?;
+        std::fs::write(
+            entry_dir.join(FLORG_TRASH_META_FILENAME),
+            serde_json::to_string_pretty(&entry)?,
+        )
+        .context("failed to write trash metadata")?;
+        std::fs::rename(&file_path, entry_dir.join("node"))
+            .with_context(|| format!("failed to move {:?} to the trash", file_path))?;
+        if commit {
+            self.add_and_commit(&format!("Deleted node {} and children", path.to_human()))?;
+        }
+        let removed_ids: Vec<String> = self
+            .paths_below(path)
+            .iter()
+            .filter_map(|p| self.nodes.remove(p))
+            .map(|n| n.id)
+            .collect();
+        self.forget_nodes(&removed_ids);
+        Ok(())
+    }
+
+    pub(crate) fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        let trash_dir = self.data_path.join(FLORG_TRASH_DIR);
+        if !trash_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut res = Vec::new();
+        for dir_entry in std::fs::read_dir(&trash_dir)?.filter_map(|x| x.ok()) {
+            let meta_filename = dir_entry.path().join(FLORG_TRASH_META_FILENAME);
+            let entry = std::fs::read_to_string(&meta_filename)
+                .context("failed to read")
+                .and_then(|raw| Ok(serde_json::from_str::<TrashEntry>(&raw)?));
+            match entry {
+                Ok(mut entry) => {
+                    entry.name = dir_entry.file_name().to_string_lossy().to_string();
+                    res.push(entry);
+                }
+                Err(e) => println!("skipping trash entry {:?}: {:?}", meta_filename, e),
+            }
+        }
+        //newest first
+        res.sort_by(|a, b| b.deleted.cmp(&a.deleted).then_with(|| a.name.cmp(&b.name)));
+        Ok(res)
+    }
+
+    /// Move a trashed subtree back into the tree - to its original path,
+    /// or to target if given. Returns where it ended up.
+    pub(crate) fn restore_from_trash(
+        &mut self,
+        name: &str,
+        target: Option<&TreePath>,
+    ) -> Result<TreePath> {
+        let entry = self
+            .list_trash()?
+            .into_iter()
+            .find(|e| e.name == name)
+            .with_context(|| format!("no trash entry '{}'", name))?;
+        let target = match target {
+            Some(target) => target.clone(),
+            None => TreePath::from_human(&entry.original_path)?,
+        };
+        if target.is_empty() {
+            bail!("Can not restore to the root node");
+        }
+        if self.get_node(&target).is_some() {
+            bail!("{} exists - restore to a different path", target.to_human());
+        }
+        let entry_dir = self.data_path.join(FLORG_TRASH_DIR).join(&entry.name);
+        let target_dir = Node::dirname_from_path(&self.data_path, &target);
+        std::fs::create_dir_all(target_dir.parent().unwrap())?;
+        std::fs::rename(entry_dir.join("node"), &target_dir)
+            .with_context(|| format!("failed to move {:?} out of the trash", entry_dir))?;
+        std::fs::remove_dir_all(&entry_dir).context("failed to remove trash entry")?;
+        self.reload_subtree(&target);
+        //the original parent might be gone as well
+        let mut parent = target.parent();
+        while !parent.is_empty() && self.get_node(&parent).is_none() {
+            self.reload_node(&parent);
+            parent = parent.parent();
+        }
+        self.add_and_commit(&format!(
+            "Restored node {} '{}' from trash (was {})",
+            target.to_human(),
+            entry.title,
+            entry.original_path
+        ))?;
+        Ok(target)
+    }
+
+    /// Permanently remove trash entries deleted before older_than (all if None).
+    /// Returns the number of entries removed.
+    pub(crate) fn empty_trash(
+        &mut self,
+        older_than: Option<chrono::NaiveDateTime>,
+    ) -> Result<usize> {
+        let trash_dir = self.data_path.join(FLORG_TRASH_DIR);
+        let mut count = 0;
+        for entry in self.list_trash()? {
+            if older_than
+                .map(|cutoff| entry.deleted < cutoff)
+                .unwrap_or(true)
+            {
+                std::fs::remove_dir_all(trash_dir.join(&entry.name))
+                    .with_context(|| format!("failed to remove trash entry {}", entry.name))?;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.add_and_commit(&format!("Emptied trash ({} entries)", count))?;
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn move_node(
+        &mut self,
+        org_path: &TreePath,
+        new_path: &TreePath,
+        commit: bool,
     ) -> Result<()> {
         if self.get_node(new_path).is_some() {
             bail!("new path already exists".to_string());
@@ -389,9 +1885,14 @@ impl Storage {
         let file_path = node.dirname(&self.data_path);
         println!("moving {:?} {:?}", &file_path, &new_file_path);
         std::fs::rename(file_path, new_file_path)?;
-        self.rename_all_children(org_path, new_path);
+        self.rename_all_children(org_path, new_path)?;
+        self.resolve_pending_links();
         if commit {
-            self.add_and_commit(&format!("moved node {org_path} to {new_path}"))?;
+            self.add_and_commit(&format!(
+                "moved node {} to {}",
+                org_path.to_human(),
+                new_path.to_human()
+            ))?;
         }
         Ok(())
     }
@@ -411,8 +1912,11 @@ impl Storage {
             .context("failed to move path to prev")?;
         self.move_node(&temp_path, path, false)
             .context("failed to move tempmove into prev")?;
-        self.make_nodes_sorted();
-        self.add_and_commit(&format!("Swapped nodes up: {} and {}", path, prev))?;
+        self.add_and_commit(&format!(
+            "Swapped nodes up: {} and {}",
+            path.to_human(),
+            prev.to_human()
+        ))?;
         Ok(())
     }
 
@@ -428,23 +1932,19 @@ impl Storage {
             .context("failed to move path to next")?;
         self.move_node(&temp_path, path, false)
             .context("failed to move tempmove into next")?;
-        self.make_nodes_sorted();
-        self.add_and_commit(&format!("Swapped nodes down: {} and {}", path, next))?;
+        self.add_and_commit(&format!(
+            "Swapped nodes down: {} and {}",
+            path.to_human(),
+            next.to_human()
+        ))?;
         Ok(())
     }
 
-    fn make_nodes_sorted(&mut self) {
-        self.nodes.sort_by(|a, b| a.path.cmp(&b.path));
-    }
-
     fn find_previous_sibling(&self, path: &TreePath) -> Option<TreePath> {
-        let node_idx = self
-            .nodes
-            .binary_search_by(|node| node.path.cmp(path))
-            .ok()?;
+        self.nodes.get(path)?;
         //find the first previous node that is at the same level (same path.len)
         //if they share their -1 prefix, return it
-        for node in self.nodes[..node_idx].iter().rev() {
+        for node in self.nodes.range(..path.clone()).rev().map(|(_, n)| n) {
             println!(
                 "Looking at {} {:?}",
                 node.path,
@@ -466,13 +1966,10 @@ impl Storage {
     }
 
     fn find_next_sibling(&self, path: &TreePath) -> Option<TreePath> {
-        let node_idx = self
-            .nodes
-            .binary_search_by(|node| node.path.cmp(path))
-            .ok()?;
-        //find the first previous node that is at the same level (same path.len)
+        self.nodes.get(path)?;
+        //find the first following node that is at the same level (same path.len)
         //if they share their -1 prefix, return it
-        for node in self.nodes[node_idx + 1..].iter() {
+        for node in self.nodes.range(path.clone()..).skip(1).map(|(_, n)| n) {
             println!(
                 "Looking at {} {:?}",
                 node.path,
@@ -493,19 +1990,78 @@ impl Storage {
         None
     }
 
-    fn rename_all_children(&mut self, org_path: &TreePath, new_path: &TreePath) {
-        //todo: fix all the links
-        for node in self.nodes.iter_mut() {
-            if node.path.starts_with(org_path) {
-                let suffix = TreePath::from(&node.path.0[org_path.len()..]);
-                println!(
-                    "Renaming {} to {}",
-                    node.path,
-                    format!("{}{}", new_path, suffix)
-                );
-                node.path = new_path.concat(&suffix);
+    fn rename_all_children(&mut self, org_path: &TreePath, new_path: &TreePath) -> Result<()> {
+        let moved: Vec<Node> = self
+            .paths_below(org_path)
+            .iter()
+            .filter_map(|p| self.nodes.remove(p))
+            .collect();
+        for mut node in moved {
+            let suffix = TreePath::from(&node.path.0[org_path.len()..]);
+            println!(
+                "Renaming {} to {}",
+                node.path,
+                format!("{}{}", new_path, suffix)
+            );
+            node.path = new_path.concat(&suffix);
+            self.ids.insert(node.id.clone(), node.path.clone());
+            self.nodes.insert(node.path.clone(), node);
+        }
+        self.rewrite_links(org_path, new_path)
+    }
+
+    /// Rewrite every path based link pointing into org_path (or below)
+    /// so that it points to new_path instead.
+    /// Only touches the files - the caller commits.
+    fn rewrite_links(&mut self, org_path: &TreePath, new_path: &TreePath) -> Result<()> {
+        let remap = |target: &str| -> Option<String> {
+            if target.starts_with(NODE_ID_PREFIX) {
+                return None; //id links survive moves
+            }
+            let target_path = TreePath::from_human(target).ok()?;
+            if target_path.starts_with(org_path) {
+                let suffix = TreePath::from(&target_path.0[org_path.len()..]);
+                Some(new_path.concat(&suffix).to_human())
+            } else {
+                None
             }
+        };
+        //only nodes linking into the moved subtree can need a rewrite
+        let sources: HashSet<TreePath> = self
+            .paths_below(new_path)
+            .iter()
+            .filter_map(|p| self.nodes.get(p))
+            .filter_map(|n| self.backlinks.get(&n.id))
+            .flatten()
+            .filter_map(|id| self.ids.get(id).cloned())
+            .collect();
+        for source in sources {
+            let node = match self.nodes.get_mut(&source) {
+                Some(node) => node,
+                None => continue,
+            };
+            if !node.links.iter().any(|target| remap(target).is_some()) {
+                continue;
+            }
+            let new_raw = LINK_REGEX
+                .replace_all(&node.raw, |caps: &regex::Captures| {
+                    let target = &caps[1];
+                    match remap(target) {
+                        Some(new_target) => format!(
+                            "<<florg:{}{}>>",
+                            new_target,
+                            caps.get(2).map(|x| x.as_str()).unwrap_or("")
+                        ),
+                        None => caps[0].to_string(),
+                    }
+                })
+                .to_string();
+            *node = Node::from_raw(node.path.clone(), node.id.clone(), new_raw);
+            let filename = node.dirname(&self.data_path).join(FLORG_FILENAME);
+            std::fs::write(filename, node.raw.trim()).context("Failed to rewrite links")?;
+            self.search_index.insert(&node.id, &node.raw);
         }
+        Ok(())
     }
 
     pub(crate) fn levels(&self, path: &TreePath) -> Vec<(String, String)> {
@@ -524,25 +2080,109 @@ impl Storage {
     }
 
     pub(crate) fn children_for(&self, path: &TreePath) -> Vec<&Node> {
-        let lp = path.len() + 1;
-        let mut res: Vec<_> = self
-            .nodes
+        self.children_paths_for(path)
             .iter()
-            .filter(|n| n.path.len() == lp && n.path.starts_with(path))
-            .collect();
-        res.sort();
-        res
+            .filter_map(|p| self.nodes.get(p))
+            .collect()
     }
 
+    /// Direct children of path, sorted.
+    /// Instead of walking the whole subtree, we jump from child to child:
+    /// after child n, the next candidate is the first node >= path/n+1.
     pub(crate) fn children_paths_for(&self, path: &TreePath) -> Vec<TreePath> {
-        let lp = path.len() + 1;
-        let mut res: Vec<_> = self
+        let mut res = Vec::new();
+        let mut start = path.append(0);
+        while let Some((candidate, _)) = self.nodes.range(start..).next() {
+            if candidate.len() <= path.len() || !candidate.starts_with(path) {
+                break;
+            }
+            let no = candidate.0[path.len()];
+            if candidate.len() == path.len() + 1 {
+                res.push(candidate.clone());
+            }
+            if no == u32::MAX {
+                break;
+            }
+            start = path.append(no + 1);
+        }
+        res
+    }
+
+    /// Nodes matching a query (see query.rs for the syntax),
+    /// with the lines that matched the query's text terms
+    pub(crate) fn query_nodes(&self, query: &str) -> Result<Vec<(&Node, Vec<(u32, String)>)>> {
+        let query = Query::parse(query, &|p| self.resolve_path(p))?;
+        let candidates: Box<dyn Iterator<Item = &Node>> = match query.restricted_to() {
+            Some(below) => Box::new(
+                self.nodes
+                    .range(below.clone()..)
+                    .take_while(|(p, _)| p.starts_with(below))
+                    .map(|(_, n)| n),
+            ),
+            None => Box::new(self.nodes.values()),
+        };
+        Ok(candidates
+            .filter(|n| !n.raw.is_empty() && query.matches(n))
+            .map(|n| (n, query.matching_lines(n)))
+            .collect())
+    }
+
+    /// Full text search (see search.rs for the syntax) at and below path,
+    /// best match first, with the matching lines of each node.
+    pub(crate) fn search_below(
+        &self,
+        path: &TreePath,
+        search_term: &str,
+        only_matching: bool,
+    ) -> Vec<(&Node, Vec<(u32, String)>)> {
+        let query = SearchQuery::parse(search_term);
+        self.search_index
+            .search(&query)
+            .into_iter()
+            .filter_map(|(id, _score)| self.get_node_by_id(&id))
+            .filter(|n| n.path.starts_with(path))
+            .map(|n| (n, query.matching_lines(&n.raw, only_matching)))
+            .collect()
+    }
+
+    /// Fuzzy match query against the titles of the nodes below 'below',
+    /// and against their 'Parent > Child' breadcrumbs, so 'proj todo'
+    /// finds the todo node of the projects node. Best match first.
+    pub(crate) fn fuzzy_find_nodes(
+        &self,
+        query: &str,
+        below: &TreePath,
+        limit: usize,
+    ) -> Vec<(i64, &Node)> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let matcher = SkimMatcherV2::default().ignore_case();
+        let mut res: Vec<(i64, &Node)> = self
             .nodes
-            .iter()
-            .filter(|n| n.path.len() == lp && n.path.starts_with(path))
-            .map(|n| n.path.clone())
+            .range(below.clone()..)
+            .take_while(|(p, _)| p.starts_with(below))
+            .filter(|(p, n)| !p.is_empty() && n.raw != "(placeholder)")
+            .filter_map(|(p, n)| {
+                let title_score = matcher.fuzzy_match(&n.header.title, query);
+                let breadcrumb = self
+                    .levels(p)
+                    .into_iter()
+                    .map(|(_, title)| title)
+                    .collect::<Vec<_>>()
+                    .join(" > ");
+                let breadcrumb_score = matcher.fuzzy_match(&breadcrumb, query);
+                title_score.max(breadcrumb_score).map(|score| (score, n))
+            })
             .collect();
-        res.sort();
+        //shallower nodes first on equal scores
+        res.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| a.1.path.len().cmp(&b.1.path.len()))
+                .then_with(|| a.1.path.cmp(&b.1.path))
+        });
+        res.truncate(limit);
         res
     }
 
@@ -589,126 +2229,645 @@ impl Storage {
         panic!("could not find an empty child. Exceeded u32::MAX");
     }
 
-    pub(crate) fn replace_node(&mut self, node: Node, commit: bool) -> Result<()> {
-        self.nodes.retain(|x| x.path != node.path);
+    pub(crate) fn replace_node(&mut self, mut node: Node, commit: bool) -> Result<()> {
+        let old_id = self.get_node(&node.path).map(|x| x.id.clone());
+        if node.id.is_empty() {
+            node.id = match old_id {
+                Some(ref id) => id.clone(),
+                None => Self::new_node_id(&self.ids, &node.path),
+            };
+        }
+        if self.settings.properties.timestamps && node.raw != "(placeholder)" {
+            let old_raw = self
+                .get_node(&node.path)
+                .map(|x| x.raw.trim())
+                .filter(|raw| *raw != "(placeholder)");
+            let now = PropertyValue::DateTime(chrono::Local::now().naive_local());
+            match old_raw {
+                None => {
+                    if !node.properties.contains_key("created") {
+                        node.set_property("created", Some(now.clone()));
+                    }
+                    node.set_property("modified", Some(now));
+                }
+                Some(old_raw) if old_raw != node.raw.trim() => {
+                    node.set_property("modified", Some(now));
+                }
+                _ => {}
+            }
+        }
+        self.nodes.remove(&node.path);
+        if let Some(old_id) = &old_id {
+            self.ids.remove(old_id);
+            self.unindex_links(old_id);
+            self.search_index.remove(old_id);
+        }
 
         let mut filename = node.dirname(&self.data_path);
         std::fs::create_dir_all(&filename).expect("failed to create directory");
         filename.push(FLORG_FILENAME);
 
-        let existed = filename.exists()
-            && (std::fs::read_to_string(&filename).unwrap_or("".to_string()) != "(placeholder)");
-        let msg = if existed {
-            format!("Changed node {} '{}'", node.path, node.header.title)
-        } else {
-            format!("Added node {} '{}'", node.path, node.header.title)
+        let existed = filename.exists()
+            && (std::fs::read_to_string(&filename).unwrap_or("".to_string()) != "(placeholder)");
+        let msg = if existed {
+            format!(
+                "Changed node {} '{}'",
+                node.path.to_human(),
+                node.header.title
+            )
+        } else {
+            format!(
+                "Added node {} '{}'",
+                node.path.to_human(),
+                node.header.title
+            )
+        };
+
+        std::fs::write(filename, node.raw.trim()).expect("Failed to write file");
+        node.write_id(&self.data_path)?;
+        if commit {
+            self.add_and_commit(&msg)?;
+        }
+        self.ids.insert(node.id.clone(), node.path.clone());
+        let (id, links, aliases) = (node.id.clone(), node.links.clone(), node.aliases());
+        self.search_index.insert(&id, &node.raw);
+        self.nodes.insert(node.path.clone(), node);
+        if let Some(old_id) = old_id.filter(|old_id| old_id != &id) {
+            self.update_aliases(&old_id, Vec::new());
+        }
+        self.update_aliases(&id, aliases);
+        self.index_links(&id, &links);
+        self.resolve_pending_links();
+        Ok(())
+    }
+
+    /// set (value = Some) or remove (value = None) a property on an existing node and commit
+    pub(crate) fn set_node_property(
+        &mut self,
+        path: &TreePath,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<()> {
+        let key = key.trim().to_lowercase();
+        let valid_key =
+            |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_';
+        if key.is_empty() || !key.chars().all(valid_key) {
+            bail!("Invalid property name '{key}'. Use letters, digits, '-' and '_'");
+        }
+        if value.map(|v| v.contains('\n')).unwrap_or(false) {
+            bail!("Property values must be a single line");
+        }
+        let value = value.map(|v| PropertyValue::parse(&key, v)).transpose()?;
+        let mut node = self.get_node(path).context("node not found")?.clone();
+        node.set_property(&key, value);
+        self.replace_node(node, false)?;
+        self.add_and_commit(&format!("Set property {key} on node {}", path.to_human()))
+    }
+
+    pub(crate) fn remove_placeholder(&mut self, path: &TreePath) {
+        let node = self.get_node(path);
+        let mut remove_path = None;
+        if let Some(node) = node {
+            if node.raw == "(placeholder)" {
+                let rd = match std::fs::read_dir(&node.dirname(&self.data_path)) {
+                    Ok(rd) => rd,
+                    Err(_) => return,
+                };
+                //anything but the node's own files (children, attachments) keeps it
+                if rd
+                    .filter_map(|x| x.ok())
+                    .filter(|entry| {
+                        let name = entry
+                            .path()
+                            .file_name()
+                            .unwrap_or_else(|| OsStr::new(""))
+                            .to_string_lossy()
+                            .to_string();
+                        name != FLORG_FILENAME
+                            && name != FLORG_ID_FILENAME
+                            && name != FLORG_CACHE_FILENAME
+                    })
+                    .next()
+                    .is_none()
+                {
+                    remove_path = Some(node.path.clone())
+                }
+            }
+        }
+        if let Some(remove_path) = remove_path {
+            self.remove_node(&remove_path);
+        }
+    }
+
+    pub fn remove_node(&mut self, path: &TreePath) {
+        let filename: PathBuf = Node::dirname_from_path(&self.data_path, path);
+        std::fs::remove_dir_all(filename).expect("Failed to unlink file");
+        if let Some(id) = self.nodes.remove(path).map(|n| n.id) {
+            self.forget_nodes(&[id]);
+        }
+        //copilot: unlink  filename
+    }
+
+    /// Queue a commit of the current state - changes within the commit window
+    /// end up in one commit, see commit_queue.rs
+    pub fn add_and_commit(&self, msg: &str) -> Result<()> {
+        self.commits.push(msg);
+        Ok(())
+    }
+
+    pub(crate) fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    pub(crate) fn commit_queue(&self) -> &CommitQueue {
+        &self.commits
+    }
+
+    /// commit the queued changes right away
+    pub fn flush_commits(&self) -> Result<()> {
+        let messages = self.commits.take_messages();
+        self.repo.add_all_and_commit(&combined_message(&messages))?;
+        match self.commits.take_error() {
+            Some(e) => Err(anyhow!("an earlier commit failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// commit the queued changes once the commit window has passed,
+    /// see commit_queue::spawn_background_commits
+    pub(crate) fn commit_queued(&self) {
+        let messages = self.commits.take_messages();
+        if messages.is_empty() {
+            //a flush beat us to it
+            return;
+        }
+        if let Err(e) = self.repo.add_all_and_commit(&combined_message(&messages)) {
+            println!("background commit failed: {:?}", e);
+            self.commits.set_error(e.to_string());
+        }
+    }
+
+    pub fn get_git_history(&self, limit: u32) -> Result<Vec<GitHistoryEntry>> {
+        self.flush_commits()?;
+        self.repo.history(limit as usize)
+    }
+
+    /// The commits that changed the text of the node at path, newest first.
+    /// Follows the node through moves.
+    pub fn get_node_history(&self, path: &TreePath, limit: u32) -> Result<Vec<NodeHistoryEntry>> {
+        self.flush_commits()?;
+        let file = path.to_filepath(Path::new("")).join(FLORG_FILENAME);
+        Ok(self
+            .repo
+            .file_history(&file, limit as usize)?
+            .into_iter()
+            .map(|(entry, file)| NodeHistoryEntry {
+                hash: entry.hash,
+                date: entry.date,
+                message: entry.message,
+                path: file
+                    .parent()
+                    .and_then(|p| TreePath::from_path(p).ok())
+                    .map(|p| p.to_human())
+                    .unwrap_or_default(),
+                file,
+            })
+            .collect())
+    }
+
+    /// The text of the node at path as it was at revision hash
+    /// (which need not have touched the node)
+    pub fn get_node_at_revision(&self, path: &TreePath, hash: &str) -> Result<String> {
+        let commit = self.repo.find_commit(hash)?;
+        //where was the node back then? The newest change at or before hash tells us.
+        let history = self.get_node_history(path, u32::MAX)?;
+        let mut entry = None;
+        for e in history.iter() {
+            if self
+                .repo
+                .is_ancestor(git2::Oid::from_str(&e.hash)?, commit.id())?
+            {
+                entry = Some(e);
+                break;
+            }
+        }
+        let not_found = || {
+            format!(
+                "node {} did not exist at revision {}",
+                path.to_human(),
+                hash
+            )
+        };
+        let entry = entry.with_context(not_found)?;
+        self.repo
+            .file_at(&commit, &entry.file)?
+            .with_context(not_found)
+    }
+
+    /// Bring back the text of the node at path from revision hash as a new commit,
+    /// leaving the rest of the tree alone
+    pub fn restore_node(&mut self, path: &TreePath, hash: &str) -> Result<()> {
+        let raw = self.get_node_at_revision(path, hash)?;
+        //hash is whatever the user typed (a branch, HEAD~2...) - name the commit
+        let short = self.repo.find_commit(hash)?.id().to_string()[..8].to_string();
+        let node = Node::new(path, &raw);
+        self.replace_node(node, false)?;
+        self.add_and_commit(&format!(
+            "Restored node {} to revision {short}",
+            path.to_human()
+        ))
+    }
+
+    /// Store the text from an editor session started at base -
+    /// following the node if it moved, merging with changes made meanwhile,
+    /// and saving it as a new sibling if those conflict.
+    pub fn save_edited_node(&mut self, base: &EditBase, edited: &str) -> Result<EditOutcome> {
+        let current = self
+            .ids
+            .get(&base.id)
+            .and_then(|path| self.get_node(path))
+            .map(|node| (node.path.clone(), node.raw.clone()));
+        let (path, current_raw) = match current {
+            Some(x) => x,
+            //deleted meanwhile - bring it back where it was, if that's still free
+            None if self.get_node(&base.path).is_none() => {
+                self.replace_node(Node::new(&base.path, edited), true)?;
+                return Ok(EditOutcome::Saved(base.path.clone()));
+            }
+            None => (base.path.clone(), "".to_string()),
+        };
+        let base_raw = base.raw.as_deref().unwrap_or("(placeholder)");
+        if current_raw.trim() == base_raw.trim() {
+            self.replace_node(Node::new(&path, edited), true)?;
+            return Ok(EditOutcome::Saved(path));
+        }
+        if self.ids.get(&base.id) == Some(&path) {
+            let base_raw = base.raw.as_deref().unwrap_or("");
+            if let Some(merged) = merge::merge3(base_raw, edited, &current_raw) {
+                self.replace_node(Node::new(&path, &merged), true)?;
+                return Ok(EditOutcome::Merged(path));
+            }
+        }
+        let parent = if path.is_empty() {
+            path.clone()
+        } else {
+            path.parent()
+        };
+        let sibling = self.find_next_empty_child(&parent);
+        self.replace_node(Node::new(&sibling, edited), true)?;
+        Ok(EditOutcome::SavedAsSibling {
+            original: path,
+            sibling,
+        })
+    }
+
+    /// The temp file of editor session `session` on node (path, id):
+    /// temp/<session>_<id>_<human path>.temp.adoc
+    pub fn edit_temp_file(&self, session: u64, path: &TreePath, id: &str) -> PathBuf {
+        self.data_path.join(FLORG_EDIT_TEMP_DIR).join(format!(
+            "{}_{}_{}.temp{}",
+            session,
+            id,
+            path.to_human().replace('/', "_"),
+            FLORG_SUFFIX
+        ))
+    }
+
+    /// (id, path when the editor was opened) from an edit_temp_file name
+    fn parse_edit_temp_name(name: &str) -> Option<(String, TreePath)> {
+        let stem = name.strip_suffix(&format!(".temp{}", FLORG_SUFFIX))?;
+        let mut parts = stem.splitn(3, '_');
+        parts.next()?.parse::<u64>().ok()?;
+        let id = parts.next()?.to_string();
+        let path = TreePath::from_human(&parts.next()?.replace('_', "/")).ok()?;
+        Some((id, path))
+    }
+
+    /// Editor temp files (see edit_temp_file) - except those in open_temp_files,
+    /// the ones with an editor still running. The node is found by id,
+    /// so it may well have moved since.
+    pub fn find_leftover_edits(&self, open_temp_files: &[PathBuf]) -> Vec<LeftoverEdit> {
+        let mut res = self.find_legacy_leftover_edits(open_temp_files);
+        let dir = match std::fs::read_dir(self.data_path.join(FLORG_EDIT_TEMP_DIR)) {
+            Ok(dir) => dir.filter_map(|e| e.ok()).collect(),
+            Err(_) => Vec::new(),
+        };
+        for entry in dir {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (id, opened_at) = match Self::parse_edit_temp_name(&name) {
+                Some(x) => x,
+                None => continue,
+            };
+            if open_temp_files.iter().any(|f| f == &entry.path()) {
+                continue;
+            }
+            let raw = match std::fs::read(entry.path()) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                Err(_) => continue,
+            };
+            let node = self.get_node_by_id(&id).filter(|_| !id.is_empty());
+            res.push(LeftoverEdit {
+                name: format!("{}/{}", FLORG_EDIT_TEMP_DIR, name),
+                content: parse_raw_content(&raw).to_string(),
+                node_exists: node.is_some(),
+                path: node.map(|n| &n.path).unwrap_or(&opened_at).to_human(),
+            });
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        res
+    }
+
+    /// Temp files of older versions, which kept them in the node folder
+    /// as <node folder>/<human path>.temp.adoc - they belong to the node of that folder
+    fn find_legacy_leftover_edits(&self, open_temp_files: &[PathBuf]) -> Vec<LeftoverEdit> {
+        let suffix = format!(".temp{}", FLORG_SUFFIX);
+        let mut res = Vec::new();
+        for entry in WalkDir::new(&self.data_path)
+            .into_iter()
+            //only node folders - not .git, .trash, temp...
+            .filter_entry(|entry| {
+                entry.depth() == 0
+                    || !entry.file_type().is_dir()
+                    || entry.file_name().to_string_lossy().parse::<u32>().is_ok()
+            })
+            .filter_map(|e| e.ok())
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type().is_file()
+                || !name.ends_with(&suffix)
+                || open_temp_files.iter().any(|f| f == entry.path())
+            {
+                continue;
+            }
+            let relative = match entry.path().strip_prefix(&self.data_path) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => continue,
+            };
+            let path = match relative
+                .parent()
+                .map(|dir| TreePath::from_file_path(&dir.to_string_lossy()))
+            {
+                Some(Ok(path)) => path,
+                _ => continue,
+            };
+            let raw = match std::fs::read(entry.path()) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                Err(_) => continue,
+            };
+            res.push(LeftoverEdit {
+                name: relative.to_string_lossy().to_string(),
+                content: parse_raw_content(&raw).to_string(),
+                node_exists: self.get_node(&path).is_some(),
+                path: path.to_human(),
+            });
+        }
+        res
+    }
+
+    /// Apply, discard or save as a new node a leftover temp file (by LeftoverEdit.name).
+    /// Returns the path of the node that was written, if any.
+    pub fn resolve_leftover_edit(
+        &mut self,
+        name: &str,
+        action: LeftoverAction,
+    ) -> Result<Option<TreePath>> {
+        let leftover = self
+            .find_leftover_edits(&[])
+            .into_iter()
+            .find(|l| l.name == name)
+            .with_context(|| format!("No leftover edit {}", name))?;
+        let path = TreePath::from_human(&leftover.path)?;
+        let written = match action {
+            LeftoverAction::Discard => None,
+            LeftoverAction::Apply => {
+                self.replace_node(Node::new(&path, &leftover.content), true)?;
+                Some(path.clone())
+            }
+            LeftoverAction::SaveAsNew => {
+                let parent = if path.is_empty() {
+                    path.clone()
+                } else {
+                    path.parent()
+                };
+                let sibling = self.find_next_empty_child(&parent);
+                self.replace_node(Node::new(&sibling, &leftover.content), true)?;
+                Some(sibling)
+            }
+        };
+        std::fs::remove_file(self.data_path.join(&leftover.name))
+            .context("failed to remove the temp file")?;
+        self.remove_placeholder(&path);
+        Ok(written)
+    }
+
+    /// Startup recovery after a crash: drop temp files that hold no changes,
+    /// and placeholders (from edit_node) without a temp file.
+    /// Returns the leftover edits that do have changes.
+    pub fn recover_edits(&mut self) -> Vec<LeftoverEdit> {
+        let mut res = Vec::new();
+        for leftover in self.find_leftover_edits(&[]) {
+            let unchanged = match TreePath::from_human(&leftover.path)
+                .ok()
+                .and_then(|path| self.get_node(&path))
+            {
+                Some(node) => {
+                    node.raw.trim() == leftover.content.trim()
+                        || node.raw == "(placeholder)" && leftover.content.trim().is_empty()
+                }
+                None => leftover.content.trim().is_empty(),
+            };
+            if unchanged {
+                self.resolve_leftover_edit(&leftover.name, LeftoverAction::Discard)
+                    .ok();
+            } else {
+                res.push(leftover);
+            }
+        }
+        let placeholders: Vec<TreePath> = self
+            .nodes
+            .values()
+            .filter(|n| n.raw == "(placeholder)")
+            .map(|n| n.path.clone())
+            .filter(|p| !res.iter().any(|l| l.path == p.to_human()))
+            .collect();
+        for path in placeholders {
+            self.remove_placeholder(&path);
+        }
+        res
+    }
+
+    /// All nodes with git conflict markers, sorted by path
+    pub fn conflicted_nodes(&self) -> Vec<&Node> {
+        self.nodes
+            .values()
+            .filter(|n| n.conflict.is_some())
+            .collect()
+    }
+
+    /// Replace a conflicted node with one side (or a hand merged text) and commit
+    pub fn resolve_conflict(
+        &mut self,
+        path: &TreePath,
+        resolution: ConflictResolution,
+    ) -> Result<()> {
+        let node = self.get_node(path).context("Node not found")?;
+        let conflict = node
+            .conflict
+            .as_ref()
+            .with_context(|| format!("Node {} has no conflict", path.to_human()))?;
+        let raw = match resolution {
+            ConflictResolution::Ours => conflict.ours.clone(),
+            ConflictResolution::Theirs => conflict.theirs.clone(),
+            ConflictResolution::Text(text) => text,
         };
-
-        std::fs::write(filename, node.raw.trim()).expect("Failed to write file");
-        if commit {
-            self.add_and_commit(&msg)?;
+        if Node::extract_conflict(&raw).is_some() {
+            bail!("The resolved text still contains conflict markers");
         }
-        self.nodes.push(node);
-        Ok(())
+        let node = Node::new(path, &raw);
+        self.replace_node(node, false)?;
+        self.add_and_commit(&format!("Resolved conflict in node {}", path.to_human()))
     }
 
-    pub(crate) fn remove_placeholder(&mut self, path: &TreePath) {
-        let node = self.get_node(path);
-        let mut remove_path = None;
-        if let Some(node) = node {
-            if node.raw == "(placeholder)" {
-                let rd =
-                    std::fs::read_dir(&node.dirname(&self.data_path)).expect("Failed to read dir");
-                if rd
-                    .filter_map(|x| x.ok())
-                    .filter(|entry| {
-                        entry
-                            .path()
-                            .file_name()
-                            .unwrap_or_else(|| OsStr::new(""))
-                            .to_string_lossy()
-                            != FLORG_FILENAME
-                    })
-                    .next()
-                    .is_none()
-                {
-                    remove_path = Some(node.path.clone())
-                }
-            }
-        }
-        if let Some(remove_path) = remove_path {
-            self.remove_node(&remove_path);
+    /// The nodes changed by commit hash (compared to its first parent), sorted by path
+    pub fn get_commit_diff(&self, hash: &str) -> Result<CommitDiff> {
+        let commit = self.repo.find_commit(hash)?;
+        let parent = commit.parent(0).ok();
+        //only node texts - and anything moving to/from .trash counts as deleted/added
+        let as_node = |file: Option<PathBuf>| {
+            file.filter(|f| f.ends_with(FLORG_FILENAME))
+                .filter(|f| Self::node_path_for_file(f).is_some())
+        };
+        let changes: Vec<(Option<PathBuf>, Option<PathBuf>)> = self
+            .repo
+            .changed_files(&commit)?
+            .into_iter()
+            .map(|(old, new)| (as_node(old), as_node(new)))
+            .filter(|change| change != &(None, None))
+            .collect();
+
+        let mut nodes = Vec::new();
+        for (old_file, new_file) in changes {
+            let old_text = match (&old_file, &parent) {
+                (Some(f), Some(parent)) => self.repo.file_at(parent, f)?,
+                _ => None,
+            };
+            let new_text = match &new_file {
+                Some(f) => self.repo.file_at(&commit, f)?,
+                None => None,
+            };
+            let old_path = old_file.as_deref().and_then(Self::node_path_for_file);
+            let new_path = new_file.as_deref().and_then(Self::node_path_for_file);
+            let status = match (&old_path, &new_path) {
+                (None, _) => "added",
+                (_, None) => "deleted",
+                (Some(a), Some(b)) if a != b && old_text == new_text => "moved",
+                _ => "modified",
+            };
+            let path = new_path.clone().or(old_path.clone()).unwrap();
+            let title = Node::from_raw(
+                path.clone(),
+                "".to_string(),
+                new_text.clone().or(old_text.clone()).unwrap_or_default(),
+            )
+            .header
+            .title;
+            let hunks = Self::diff_hunks(
+                old_text.as_deref().unwrap_or(""),
+                new_text.as_deref().unwrap_or(""),
+            );
+            nodes.push(NodeDiff {
+                path: path.to_human(),
+                old_path: old_path.filter(|p| p != &path).map(|p| p.to_human()),
+                title,
+                status: status.to_string(),
+                old_text,
+                new_text,
+                hunks,
+            });
         }
+        nodes.sort_by(|a, b| {
+            TreePath::from_human(&a.path)
+                .ok()
+                .cmp(&TreePath::from_human(&b.path).ok())
+        });
+        let entry = Repository::history_entry(&commit);
+        Ok(CommitDiff {
+            hash: entry.hash,
+            date: entry.date,
+            message: entry.message,
+            nodes,
+        })
     }
 
-    pub fn remove_node(&mut self, path: &TreePath) {
-        let filename: PathBuf = Node::dirname_from_path(&self.data_path, path);
-        std::fs::remove_dir_all(filename).expect("Failed to unlink file");
-        self.nodes.retain(|x| &x.path != path);
-        //copilot: unlink  filename
+    /// '0/1/node.adoc' -> TreePath [0, 1]. None for files outside the tree (e.g. in .trash)
+    fn node_path_for_file(file: &Path) -> Option<TreePath> {
+        TreePath::from_path(file.parent()?).ok()
     }
 
-    pub fn add_and_commit(&self, msg: &str) -> Result<()> {
-        std::process::Command::new(&self.git_binary)
-            .arg("add")
-            .arg(".")
-            .current_dir(&self.data_path)
-            .status()
-            .context("git add failed")?;
-        std::process::Command::new(&self.git_binary)
-            .arg("commit")
-            .arg("-m")
-            .arg(msg)
-            .current_dir(&self.data_path)
-            .status()
-            .context("git add failed")?;
-        Ok(())
+    fn diff_hunks(old: &str, new: &str) -> Vec<DiffHunk> {
+        let diff = similar::TextDiff::from_lines(old, new);
+        diff.grouped_ops(3)
+            .iter()
+            .map(|group| {
+                let first = group.first().unwrap();
+                let last = group.last().unwrap();
+                let old_range = first.old_range().start..last.old_range().end;
+                let new_range = first.new_range().start..last.new_range().end;
+                let lines = group
+                    .iter()
+                    .flat_map(|op| diff.iter_changes(op))
+                    .map(|change| DiffLine {
+                        tag: match change.tag() {
+                            similar::ChangeTag::Equal => "equal",
+                            similar::ChangeTag::Insert => "insert",
+                            similar::ChangeTag::Delete => "delete",
+                        }
+                        .to_string(),
+                        old_line: change.old_index().map(|x| x + 1),
+                        new_line: change.new_index().map(|x| x + 1),
+                        text: change.value().trim_end_matches('\n').to_string(),
+                    })
+                    .collect();
+                DiffHunk {
+                    old_start: old_range.start + 1,
+                    old_len: old_range.len(),
+                    new_start: new_range.start + 1,
+                    new_len: new_range.len(),
+                    lines,
+                }
+            })
+            .collect()
     }
 
-    pub fn get_git_history(&self, limit: u32) -> Result<Vec<GitHistoryEntry>> {
-        let raw = std::process::Command::new(&self.git_binary)
-            .arg("log")
-            .arg("--date=iso")
-            .arg("--pretty=%ah%n%H%n%s%n")
-            .arg(format!("-n{limit}"))
-            .current_dir(&self.data_path)
-            .output()
-            .context("git log failed")?
-            .stdout;
-        let raw = String::from_utf8(raw).context("git log output not utf8")?;
-        let mut res: Vec<GitHistoryEntry> = Vec::new();
-        //every line is valid json, but it's not a json array...
-        for block in raw.split("\n\n") {
-            let block = block.trim();
-            if !block.is_empty() {
-                let lines: Vec<_> = block.split("\n").collect();
-                let parsed: GitHistoryEntry = GitHistoryEntry {
-                    hash: lines[1].to_string(),
-                    date: lines[0].to_string(),
-                    message: lines[2].to_string(),
-                };
-                res.push(parsed)
+    /// Undo everything after commit hash by reverting the commits since, as one new commit.
+    /// Nothing is lost - git_redo brings it back.
+    pub fn git_undo(&mut self, hash: &str) -> Result<()> {
+        self.flush_commits()?;
+        {
+            let target = self.repo.find_commit(hash)?;
+            let commits = self.repo.commits_since(&target)?;
+            if commits.is_empty() {
+                bail!("Nothing to undo - {} is the current state", hash);
             }
+            self.repo
+                .revert_commits(&commits, &format!("{}{}", UNDO_COMMIT_PREFIX, hash))?;
         }
-        Ok(res)
+        self.reload();
+        Ok(())
     }
 
-    pub fn git_undo(&mut self, hash: &str) -> Result<()> {
-        std::process::Command::new(&self.git_binary)
-            .arg("reset")
-            .arg("--hard")
-            .arg(hash)
-            .current_dir(&self.data_path)
-            .status()
-            .context("git reset hard hash failed")?;
-        std::process::Command::new(&self.git_binary)
-            .arg("reset")
-            .arg("HEAD@{1}")
-            .current_dir(&self.data_path)
-            .status()
-            .context("git reset to head failed")?;
-
-        self.add_and_commit(&format!("Undo to state of {}", hash))?;
+    /// Revert an undo commit (the last commit if hash is None)
+    pub fn git_redo(&mut self, hash: Option<&str>) -> Result<()> {
+        self.flush_commits()?;
+        {
+            let undo = self.repo.find_commit(hash.unwrap_or("HEAD"))?;
+            let message = undo.message().unwrap_or("").trim().to_string();
+            if !message.starts_with(UNDO_COMMIT_PREFIX) {
+                bail!("Nothing to redo - '{}' is not an undo", message);
+            }
+            self.repo
+                .revert_commits(&[undo], &format!("Redo: revert '{}'", message))?;
+        }
         self.reload();
         Ok(())
     }
@@ -799,7 +2958,7 @@ impl Storage {
             .map(|(ii, (_title, old_path))| (old_path, path.append(ii as u32)))
             .collect();
         self.remap_children(path.clone(), children)?;
-        self.add_and_commit(&format!("Sorted children of {path}"))?;
+        self.add_and_commit(&format!("Sorted children of {}", path.to_human()))?;
         Ok(())
     }
 
@@ -835,8 +2994,6 @@ impl Storage {
             let temp_path = temp_root.append(*old_path.0.last().unwrap());
             self.move_node(&temp_path, &new_path, false)?;
         }
-
-        self.make_nodes_sorted();
         Ok(())
     }
 
@@ -862,14 +3019,50 @@ impl Storage {
 
 impl Node {
     pub fn new(path: &TreePath, raw: &str) -> Node {
-        let header = Self::extract_header(raw);
+        Self::from_raw(path.clone(), "".to_string(), raw.to_string())
+    }
+
+    pub(crate) fn from_raw(path: TreePath, id: String, raw: String) -> Node {
+        let conflict = Self::extract_conflict(&raw);
+        //a conflicted node shows our side - rather than markers in the title
+        let effective = conflict.as_ref().map(|c| &c.ours[..]).unwrap_or(&raw);
+        let (properties, body) = Self::split_properties(effective);
+        let header = Self::extract_header(body);
+        let links = Self::extract_links(effective);
         Node {
-            path: path.clone(),
+            path,
+            id,
             header,
-            raw: raw.to_string(),
+            properties,
+            links,
+            raw,
+            conflict,
+        }
+    }
+
+    //stand in for folders that have child nodes but no FLORG_FILENAME
+    fn empty(path: TreePath) -> Node {
+        Node {
+            path,
+            id: "".to_string(),
+            header: Header {
+                title: "(empty node)".to_string(),
+                first_paragraph: "".to_string(),
+                has_more_content: false,
+            },
+            raw: "".to_string(),
+            properties: BTreeMap::new(),
+            links: Vec::new(),
+            conflict: None,
         }
     }
 
+    fn write_id(&self, data_path: &PathBuf) -> Result<()> {
+        let dir = self.dirname(data_path);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(FLORG_ID_FILENAME), &self.id).context("failed to write node id")
+    }
+
     pub fn dirname(&self, data_path: &PathBuf) -> PathBuf {
         Node::dirname_from_path(data_path, &self.path)
     }
@@ -878,10 +3071,167 @@ impl Node {
         path.to_filepath(data_path)
     }
 
-    fn parse(path: TreePath, file_path: &Path) -> Node {
-        let raw = std::fs::read_to_string(file_path).unwrap();
-        let header = Self::extract_header(&raw);
-        Node { path, header, raw }
+    fn parse(path: TreePath, file_path: &Path) -> Result<Node> {
+        let bytes = std::fs::read(file_path)
+            .with_context(|| format!("failed to read {}", file_path.display()))?;
+        //don't lose a node over a stray non-utf8 byte
+        let raw = String::from_utf8_lossy(&bytes).to_string();
+        let id = std::fs::read_to_string(file_path.with_file_name(FLORG_ID_FILENAME))
+            .map(|x| x.trim().to_string())
+            .unwrap_or_default();
+        Ok(Self::from_raw(path, id, raw))
+    }
+
+    /// Node::parse, but an unreadable node becomes an empty one instead of an error
+    fn parse_or_empty(path: TreePath, file_path: &Path) -> Node {
+        match Self::parse(path.clone(), file_path) {
+            Ok(node) => node,
+            Err(e) => {
+                println!("could not parse node {}: {:?}", path, e);
+                Self::empty(path)
+            }
+        }
+    }
+
+    /// Split text with git conflict markers into our and their version.
+    /// None if there are no (complete) conflict blocks.
+    fn extract_conflict(raw: &str) -> Option<Conflict> {
+        if !raw.contains("<<<<<<<") {
+            return None;
+        }
+        enum Section {
+            Both,
+            Ours,
+            Base,
+            Theirs,
+        }
+        let mut section = Section::Both;
+        let mut found = false;
+        let mut ours = String::new();
+        let mut theirs = String::new();
+        for line in raw.split_inclusive('\n') {
+            let marker = |m: &str| {
+                line.starts_with(m)
+                    && line[m.len()..]
+                        .chars()
+                        .next()
+                        .map(|c| c.is_whitespace())
+                        .unwrap_or(true)
+            };
+            section = match section {
+                Section::Both if marker("<<<<<<<") => Section::Ours,
+                Section::Ours if marker("|||||||") => Section::Base,
+                Section::Ours | Section::Base if marker("=======") => Section::Theirs,
+                Section::Theirs if marker(">>>>>>>") => {
+                    found = true;
+                    Section::Both
+                }
+                Section::Both => {
+                    ours.push_str(line);
+                    theirs.push_str(line);
+                    Section::Both
+                }
+                Section::Ours => {
+                    ours.push_str(line);
+                    Section::Ours
+                }
+                Section::Base => Section::Base,
+                Section::Theirs => {
+                    theirs.push_str(line);
+                    Section::Theirs
+                }
+            };
+        }
+        match section {
+            Section::Both if found => Some(Conflict { ours, theirs }),
+            //unterminated - probably just text that looks like a marker
+            _ => None,
+        }
+    }
+
+    /// split raw into the properties block and the rest (the body, starting with the title)
+    fn split_properties(raw: &str) -> (BTreeMap<String, PropertyValue>, &str) {
+        let mut properties = BTreeMap::new();
+        let mut body = raw;
+        //split_inclusive, so \r\n line ends are skipped completely
+        while let Some(line) = body.split_inclusive('\n').next() {
+            match PROPERTY_REGEX.captures(line.trim_end_matches(&['\r', '\n'][..])) {
+                Some(caps) => {
+                    let key = caps[1].to_lowercase();
+                    let value = PropertyValue::parse(&key, &caps[2])
+                        .unwrap_or_else(|_| PropertyValue::Text(caps[2].trim().to_string()));
+                    properties.insert(key, value);
+                    body = &body[line.len()..];
+                }
+                None => break,
+            }
+        }
+        (properties, body)
+    }
+
+    pub fn body(&self) -> &str {
+        Self::split_properties(&self.raw).1
+    }
+
+    /// the valid names in an ':alias:' value - lowercase, without a leading '@'
+    fn parse_aliases(value: &str) -> impl Iterator<Item = String> + '_ {
+        value
+            .split(',')
+            .map(|alias| {
+                alias
+                    .trim()
+                    .trim_start_matches(NODE_ALIAS_PREFIX)
+                    .to_lowercase()
+            })
+            .filter(|alias| {
+                !alias.is_empty()
+                    && alias
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            })
+    }
+
+    /// the aliases this node declares with ':alias: name, other-name'
+    pub fn aliases(&self) -> Vec<String> {
+        match self.properties.get("alias") {
+            Some(value) => Self::parse_aliases(&value.to_string()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// set (or with None, remove) a property. Only its line in the properties
+    /// block of raw changes, the other lines stay as written.
+    pub fn set_property(&mut self, key: &str, value: Option<PropertyValue>) {
+        let key = key.to_lowercase();
+        let body = self.body();
+        let block = &self.raw[..self.raw.len() - body.len()];
+        let newline = if self.raw.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut new_line = value.map(|value| format!(":{key}: {value}{newline}"));
+        let mut raw = String::new();
+        for line in block.split_inclusive('\n') {
+            let is_key = PROPERTY_REGEX
+                .captures(line.trim_end_matches(&['\r', '\n'][..]))
+                .map(|caps| caps[1].to_lowercase() == key)
+                .unwrap_or(false);
+            if is_key {
+                //the first one is replaced, later duplicates are dropped
+                raw.push_str(&new_line.take().unwrap_or_default());
+            } else {
+                raw.push_str(line);
+            }
+        }
+        if let Some(new_line) = new_line {
+            if !raw.is_empty() && !raw.ends_with('\n') {
+                raw.push_str(newline);
+            }
+            raw.push_str(&new_line);
+        }
+        raw.push_str(body);
+        *self = Self::from_raw(self.path.clone(), self.id.clone(), raw);
     }
 
     fn extract_header(contents: &str) -> Header {
@@ -889,7 +3239,7 @@ impl Node {
             Some((first_line, _)) => first_line,
             _ => contents,
         };
-        let title = untrimmed_title.trim_start_matches("=").trim_start();
+        let title = untrimmed_title.trim_start_matches("=").trim();
         let (first_para, has_more) = match contents.split_once("\n\n") {
             Some((first_para, _)) => {
                 (
@@ -930,6 +3280,13 @@ impl Node {
         res
     }
 
+    pub fn extract_links(text: &str) -> Vec<String> {
+        LINK_REGEX
+            .captures_iter(text)
+            .map(|c| c[1].trim().to_string())
+            .collect()
+    }
+
     pub fn get_tags(&self) -> Vec<String> {
         return Node::extract_tags(&self.raw).into_iter().collect();
     }
@@ -952,3 +3309,828 @@ impl PartialEq for Node {
         self.path == other.path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A storage on a fresh git repository, with (folder, node text) nodes
+    pub(crate) fn storage_with(nodes: &[(&str, &str)]) -> (tempfile::TempDir, Storage) {
+        let dir = tempfile::tempdir().unwrap();
+        for (folder, raw) in nodes {
+            let node_dir = dir.path().join(folder);
+            std::fs::create_dir_all(&node_dir).unwrap();
+            std::fs::write(node_dir.join(FLORG_FILENAME), raw).unwrap();
+        }
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "florg test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let storage = Storage::new(dir.path().to_path_buf()).unwrap();
+        (dir, storage)
+    }
+
+    fn tp(human: &str) -> TreePath {
+        TreePath::from_human(human).unwrap()
+    }
+
+    fn backlinks(s: &Storage, path: &str) -> Vec<String> {
+        s.get_backlinks(&tp(path))
+            .iter()
+            .map(|n| n.path.to_human())
+            .collect()
+    }
+
+    #[test]
+    fn backlinks_to_nodes_that_appear_later() {
+        let (_dir, mut s) = storage_with(&[("0", "A\n\nsee <<florg:B>> and <<florg:C>>")]);
+        assert!(backlinks(&s, "A").is_empty());
+
+        s.replace_node(Node::from_raw(tp("B"), String::new(), "B".into()), false)
+            .unwrap();
+        assert_eq!(backlinks(&s, "B"), ["A"]);
+
+        s.replace_node(Node::from_raw(tp("D"), String::new(), "D".into()), false)
+            .unwrap();
+        s.move_node(&tp("D"), &tp("C"), false).unwrap();
+        assert_eq!(backlinks(&s, "C"), ["A"]);
+
+        s.delete_node(&tp("B"), false).unwrap();
+        assert!(s.get_node(&tp("B")).is_none());
+        let name = s.list_trash().unwrap()[0].name.clone();
+        s.restore_from_trash(&name, None).unwrap();
+        assert_eq!(backlinks(&s, "B"), ["A"]);
+    }
+
+    fn raw_on_disk(dir: &tempfile::TempDir, folder: &str) -> String {
+        std::fs::read_to_string(dir.path().join(folder).join(FLORG_FILENAME)).unwrap()
+    }
+
+    #[test]
+    fn links_are_rewritten_on_move() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("0/0", "AA"), ("1", "B")]);
+        let a_id = s.get_node(&tp("A")).unwrap().id.clone();
+        let links = format!("B\n\n<<florg:A>> <<florg:AA,the child>> <<florg:id:{a_id}>>");
+        s.replace_node(Node::new(&tp("B"), &links), false).unwrap();
+
+        s.move_node(&tp("A"), &tp("C"), false).unwrap();
+        let expected = format!("B\n\n<<florg:C>> <<florg:CA,the child>> <<florg:id:{a_id}>>");
+        assert_eq!(s.get_node(&tp("B")).unwrap().raw, expected);
+        assert_eq!(raw_on_disk(&dir, "1"), expected);
+        assert_eq!(backlinks(&s, "CA"), ["B"]);
+    }
+
+    #[test]
+    fn links_are_rewritten_on_swap() {
+        let (dir, mut s) = storage_with(&[
+            ("0", "A"),
+            ("1", "B"),
+            ("2", "C\n\n<<florg:A,first>> <<florg:B>>"),
+        ]);
+        s.swap_node_with_next(&tp("A")).unwrap();
+        let expected = "C\n\n<<florg:B,first>> <<florg:A>>";
+        assert_eq!(s.get_node(&tp("C")).unwrap().raw, expected);
+        assert_eq!(raw_on_disk(&dir, "2"), expected);
+
+        s.swap_node_with_previous(&tp("B")).unwrap();
+        let expected = "C\n\n<<florg:A,first>> <<florg:B>>";
+        assert_eq!(s.get_node(&tp("C")).unwrap().raw, expected);
+        assert_eq!(raw_on_disk(&dir, "2"), expected);
+    }
+
+    #[test]
+    fn links_are_rewritten_on_sort() {
+        let (dir, mut s) = storage_with(&[
+            ("0", "Z"),
+            ("0/0", "P 10"),
+            ("0/1", "P 9"),
+            ("0/1/0", "P 9 child"),
+            ("1", "Y"),
+        ]);
+        let p10_id = s.get_node(&tp("AA")).unwrap().id.clone();
+        let links = format!("Y\n\n<<florg:AA,ten>> <<florg:ABA>> <<florg:id:{p10_id}>>");
+        s.replace_node(Node::new(&tp("B"), &links), false).unwrap();
+
+        s.sort_children(&tp("A")).unwrap();
+        assert_eq!(s.get_node(&tp("AA")).unwrap().header.title, "P 9");
+        let expected = format!("Y\n\n<<florg:AB,ten>> <<florg:AAA>> <<florg:id:{p10_id}>>");
+        assert_eq!(s.get_node(&tp("B")).unwrap().raw, expected);
+        assert_eq!(raw_on_disk(&dir, "1"), expected);
+    }
+
+    #[test]
+    fn trash_restore_elsewhere_and_empty() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B"), ("2", "C"), ("2/0", "CA")]);
+        std::fs::write(dir.path().join(".gitignore"), ".trash/\n").unwrap();
+        s.delete_node(&tp("B"), true).unwrap();
+        s.delete_node(&tp("C"), true).unwrap();
+        s.flush_commits().unwrap();
+        let trash = s.list_trash().unwrap();
+        assert_eq!(trash.len(), 2);
+        //ignored, so not committed (and not synced)
+        let meta = Path::new(FLORG_TRASH_DIR)
+            .join(&trash[0].name)
+            .join(FLORG_TRASH_META_FILENAME);
+        let head = s.repository().find_commit("HEAD").unwrap();
+        assert_eq!(s.repository().file_at(&head, &meta).unwrap(), None);
+        drop(head);
+
+        //B was deleted ten days ago
+        let b = trash.iter().find(|e| e.original_path == "B").unwrap();
+        let mut old = b.clone();
+        old.deleted -= chrono::Duration::days(10);
+        std::fs::write(
+            dir.path()
+                .join(FLORG_TRASH_DIR)
+                .join(&b.name)
+                .join(FLORG_TRASH_META_FILENAME),
+            serde_json::to_string(&old).unwrap(),
+        )
+        .unwrap();
+        let cutoff = chrono::Local::now().naive_local() - chrono::Duration::days(1);
+        assert_eq!(s.empty_trash(Some(cutoff)).unwrap(), 1);
+        let trash = s.list_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].original_path, "C");
+        assert_eq!(trash[0].node_count, 2);
+
+        assert!(s
+            .restore_from_trash(&trash[0].name, Some(&tp("A")))
+            .is_err());
+        let restored = s
+            .restore_from_trash(&trash[0].name, Some(&tp("D")))
+            .unwrap();
+        assert_eq!(restored, tp("D"));
+        assert_eq!(s.get_node(&tp("D")).unwrap().raw, "C");
+        assert_eq!(s.get_node(&tp("DA")).unwrap().raw, "CA");
+        assert!(s.get_node(&tp("C")).is_none());
+        assert!(s.list_trash().unwrap().is_empty());
+        assert_eq!(s.empty_trash(None).unwrap(), 0);
+    }
+
+    #[test]
+    fn restore_a_node_from_its_history() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B one")]);
+        s.add_and_commit("first").unwrap();
+        s.flush_commits().unwrap();
+        let first = s.get_git_history(1).unwrap()[0].hash.clone();
+        //one commit each - queued changes would end up in a single one
+        s.replace_node(Node::new(&tp("B"), "B two"), true).unwrap();
+        s.flush_commits().unwrap();
+        s.move_node(&tp("B"), &tp("C"), true).unwrap();
+        s.flush_commits().unwrap();
+        s.replace_node(Node::new(&tp("A"), "A changed"), true)
+            .unwrap();
+
+        let history = s.get_node_history(&tp("C"), 10).unwrap();
+        let paths: Vec<&str> = history.iter().map(|e| &e.path[..]).collect();
+        assert_eq!(paths, ["C", "B", "B"]);
+        assert_eq!(s.get_node_at_revision(&tp("C"), &first).unwrap(), "B one");
+        let error = s.get_node_at_revision(&tp("D"), &first).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("node D did not exist at revision {first}")
+        );
+
+        //any rev spec, not only hashes
+        let git = git2::Repository::open(dir.path()).unwrap();
+        let target = git.revparse_single(&first).unwrap();
+        git.tag_lightweight("überprüft", &target, false).unwrap();
+        s.restore_node(&tp("C"), "überprüft").unwrap();
+        s.flush_commits().unwrap();
+        assert_eq!(s.get_node(&tp("C")).unwrap().raw, "B one");
+        assert_eq!(raw_on_disk(&dir, "2"), "B one");
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, "A changed");
+        assert_eq!(raw_on_disk(&dir, "0"), "A changed");
+        assert!(s.get_node(&tp("B")).is_none());
+        let last = &s.get_git_history(1).unwrap()[0];
+        assert_eq!(
+            last.message,
+            format!("Restored node C to revision {}", &first[..8])
+        );
+    }
+
+    #[test]
+    fn commit_diff_of_added_changed_moved_and_deleted_nodes() {
+        let a: Vec<String> = (1..=10).map(|ii| format!("line {ii}")).collect();
+        let (_dir, mut s) = storage_with(&[("0", &a.join("\n")), ("1", "B"), ("2", "C")]);
+        s.add_and_commit("first").unwrap();
+        s.flush_commits().unwrap();
+
+        let mut changed_a = a.clone();
+        changed_a[4] = "line five".to_string();
+        s.replace_node(Node::new(&tp("A"), &changed_a.join("\n")), true)
+            .unwrap();
+        s.replace_node(Node::new(&tp("D"), "D"), true).unwrap();
+        s.move_node(&tp("B"), &tp("E"), true).unwrap();
+        //no .gitignore here - git sees C renamed into .trash
+        s.delete_node(&tp("C"), true).unwrap();
+        s.flush_commits().unwrap();
+        let diff = s.get_commit_diff("HEAD").unwrap();
+
+        let summary: Vec<(&str, Option<&str>, &str)> = diff
+            .nodes
+            .iter()
+            .map(|n| (&n.path[..], n.old_path.as_deref(), &n.status[..]))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("A", None, "modified"),
+                ("C", None, "deleted"),
+                ("D", None, "added"),
+                ("E", Some("B"), "moved"),
+            ]
+        );
+        let hunks = &diff.nodes[0].hunks;
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(
+            (
+                hunks[0].old_start,
+                hunks[0].old_len,
+                hunks[0].new_start,
+                hunks[0].new_len
+            ),
+            (2, 7, 2, 7)
+        );
+        let changes: Vec<(&str, Option<usize>, Option<usize>, &str)> = hunks[0]
+            .lines
+            .iter()
+            .filter(|l| l.tag != "equal")
+            .map(|l| (&l.tag[..], l.old_line, l.new_line, &l.text[..]))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("delete", Some(5), None, "line 5"),
+                ("insert", None, Some(5), "line five"),
+            ]
+        );
+
+        let deleted = &diff.nodes[1];
+        assert_eq!(deleted.title, "C");
+        assert_eq!(
+            (deleted.old_text.as_deref(), deleted.new_text.as_deref()),
+            (Some("C"), None)
+        );
+        let added = &diff.nodes[2];
+        assert_eq!(
+            (added.old_text.as_deref(), added.new_text.as_deref()),
+            (None, Some("D"))
+        );
+        assert_eq!(added.hunks[0].lines[0].tag, "insert");
+        assert!(diff.nodes[3].hunks.is_empty());
+    }
+
+    #[test]
+    fn redo_reverts_the_undo() {
+        let (dir, mut s) = storage_with(&[("0", "A")]);
+        s.add_and_commit("first").unwrap();
+        s.flush_commits().unwrap();
+        let first = s.get_git_history(1).unwrap()[0].hash.clone();
+        s.replace_node(Node::new(&tp("A"), "A changed"), true)
+            .unwrap();
+        s.replace_node(Node::new(&tp("B"), "B"), true).unwrap();
+
+        s.git_undo(&first).unwrap();
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, "A");
+        assert!(s.get_node(&tp("B")).is_none());
+
+        s.git_redo(None).unwrap();
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, "A changed");
+        assert_eq!(raw_on_disk(&dir, "0"), "A changed");
+        assert_eq!(s.get_node(&tp("B")).unwrap().raw, "B");
+        //the redo is no undo
+        assert!(s.git_redo(None).is_err());
+
+        //an older undo, by hash
+        s.git_undo(&first).unwrap();
+        let undo = s.get_git_history(1).unwrap()[0].hash.clone();
+        s.replace_node(Node::new(&tp("C"), "C"), true).unwrap();
+        s.git_redo(Some(&undo)).unwrap();
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, "A changed");
+        assert_eq!(s.get_node(&tp("C")).unwrap().raw, "C");
+    }
+
+    #[test]
+    fn conflicted_node_ids_keep_ours() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B")]);
+        let b_id = s.get_node(&tp("B")).unwrap().id.clone();
+        let conflicted =
+            |ours: &str| format!("<<<<<<< ours\n{ours}\n=======\nffff0000\n>>>>>>> theirs\n");
+        std::fs::write(dir.path().join("0/node.id"), conflicted("1234abcd")).unwrap();
+        //our id is B's - that one needs a new id
+        std::fs::write(dir.path().join("1/node.id"), conflicted(&b_id)).unwrap();
+        std::fs::create_dir_all(dir.path().join("2")).unwrap();
+        std::fs::write(dir.path().join("2/node.adoc"), "C").unwrap();
+        std::fs::write(dir.path().join("2/node.id"), conflicted(&b_id)).unwrap();
+        s.reload();
+
+        assert_eq!(s.get_node(&tp("A")).unwrap().id, "1234abcd");
+        assert_eq!(s.get_node(&tp("B")).unwrap().id, b_id);
+        let c_id = s.get_node(&tp("C")).unwrap().id.clone();
+        assert!(c_id != b_id && c_id.len() == 8, "{c_id}");
+        for (folder, id) in [("0", "1234abcd"), ("1", &b_id[..]), ("2", &c_id[..])] {
+            let on_disk = std::fs::read_to_string(dir.path().join(folder).join("node.id"));
+            assert_eq!(on_disk.unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn fuzzy_find_ranks_titles_and_breadcrumbs() {
+        let (_dir, s) = storage_with(&[
+            ("0", "Projects"),
+            ("0/0", "Todo"),
+            ("1", "The overdue doc"),
+            ("2", "Shopping"),
+            ("2/0", "Todo"),
+            ("3", "(placeholder)"),
+        ]);
+        let find = |query: &str, below: &str, limit: usize| -> Vec<String> {
+            s.fuzzy_find_nodes(query, &TreePath::from_human(below).unwrap(), limit)
+                .iter()
+                .map(|(_, n)| n.path.to_human())
+                .collect()
+        };
+        //the contiguous matches beat the scattered one, equal scores go by path
+        assert_eq!(find("todo", "", 10), ["AA", "CA", "B"]);
+        assert_eq!(find("todo", "", 1), ["AA"]);
+        assert_eq!(find("todo", "C", 10), ["CA"]);
+        //'shop todo' is only in the breadcrumb 'Shopping > Todo'
+        assert_eq!(find("shop todo", "", 10), ["CA"]);
+        assert_eq!(find("shop", "", 10), ["C", "CA"]);
+        assert!(find("  ", "", 10).is_empty());
+        assert!(find("placeholder", "", 10).is_empty());
+    }
+
+    #[test]
+    fn reload_updates_only_the_changed_nodes() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B"), ("1/0", "BA")]);
+        let b_id = s.get_node(&tp("B")).unwrap().id.clone();
+        let ba_id = s.get_node(&tp("BA")).unwrap().id.clone();
+
+        std::fs::write(
+            dir.path().join("0").join(FLORG_FILENAME),
+            "A\n\n<<florg:CA>>",
+        )
+        .unwrap();
+        assert_eq!(s.reload_node(&tp("A")), vec![tp("A")]);
+        assert!(backlinks(&s, "BA").is_empty());
+
+        //moved by hand (git mv): the new folder is seen before the old one is gone
+        std::fs::rename(dir.path().join("1"), dir.path().join("2")).unwrap();
+        assert_eq!(s.reload_subtree(&tp("C")), vec![tp("C"), tp("CA")]);
+        assert_eq!(s.reload_subtree(&tp("B")), vec![tp("B"), tp("BA")]);
+        assert_eq!(s.get_node(&tp("C")).unwrap().id, b_id);
+        assert_eq!(s.get_node_by_id(&ba_id).unwrap().path, tp("CA"));
+        assert_eq!(backlinks(&s, "CA"), ["A"]);
+
+        std::fs::remove_dir_all(dir.path().join("2")).unwrap();
+        s.reload_subtree(&tp("C"));
+        assert!(s.get_node_by_id(&b_id).is_none());
+        std::fs::create_dir_all(dir.path().join("2").join("0")).unwrap();
+        std::fs::write(dir.path().join("2/0").join(FLORG_FILENAME), "new CA").unwrap();
+        s.reload_subtree(&tp("C"));
+        assert_eq!(backlinks(&s, "CA"), ["A"]);
+    }
+
+    #[test]
+    fn properties_with_crlf_line_ends() {
+        let raw = ":status: open\r\n:priority: 2\r\nTitle\r\n\r\nThe text";
+        let (dir, mut s) = storage_with(&[("0", raw)]);
+        let node = s.get_node(&tp("A")).unwrap();
+        assert_eq!(
+            node.properties.get("status"),
+            Some(&PropertyValue::Text("open".into()))
+        );
+        assert_eq!(
+            node.properties.get("priority"),
+            Some(&PropertyValue::Number(2))
+        );
+        assert_eq!(node.body(), "Title\r\n\r\nThe text");
+
+        s.set_node_property(&tp("A"), "due", Some("2026-01-02"))
+            .unwrap();
+        let on_disk = std::fs::read_to_string(dir.path().join("0").join(FLORG_FILENAME)).unwrap();
+        assert_eq!(
+            on_disk,
+            ":status: open\r\n:priority: 2\r\n:due: 2026-01-02\r\nTitle\r\n\r\nThe text"
+        );
+        assert_eq!(s.get_node(&tp("A")).unwrap().header.title, "Title");
+    }
+
+    #[test]
+    fn setting_a_property_keeps_the_other_lines() {
+        let raw = ":TOC:\n:due: 2026-01-01T10:00:30\n:priority: 05\nTitle\n\nThe text";
+        let (dir, mut s) = storage_with(&[("0", raw)]);
+        s.set_node_property(&tp("A"), "status", Some("open"))
+            .unwrap();
+        let expected =
+            ":TOC:\n:due: 2026-01-01T10:00:30\n:priority: 05\n:status: open\nTitle\n\nThe text";
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, expected);
+        assert_eq!(raw_on_disk(&dir, "0"), expected);
+
+        s.set_node_property(&tp("A"), "Priority", Some("3"))
+            .unwrap();
+        s.set_node_property(&tp("A"), "toc", None).unwrap();
+        assert_eq!(
+            s.get_node(&tp("A")).unwrap().raw,
+            ":due: 2026-01-01T10:00:30\n:priority: 3\n:status: open\nTitle\n\nThe text"
+        );
+
+        //a node that is nothing but properties
+        let mut node = Node::new(&tp("B"), ":status: open");
+        node.set_property("priority", Some(PropertyValue::Number(1)));
+        assert_eq!(node.raw, ":status: open\n:priority: 1\n");
+    }
+
+    #[test]
+    fn property_keys_are_validated() {
+        let (_dir, mut s) = storage_with(&[("0", "A")]);
+        for key in ["a:b", "", "a b", "ä"] {
+            assert!(
+                s.set_node_property(&tp("A"), key, Some("x")).is_err(),
+                "{key}"
+            );
+        }
+        s.set_node_property(&tp("A"), "Some_key-2", Some("x"))
+            .unwrap();
+        assert_eq!(s.get_node(&tp("A")).unwrap().raw, ":some_key-2: x\nA");
+    }
+
+    #[test]
+    fn invalid_settings_are_reported() {
+        let (dir, mut s) = storage_with(&[("0", "A")]);
+        assert_eq!(s.settings_error(), None);
+        let settings = dir.path().join("settings.toml");
+        std::fs::write(&settings, "[tags]\nx = \"red\"\n[editor]\ncommand = []\n").unwrap();
+        s.reload();
+        assert!(s.settings_error().unwrap().contains("editor.command"));
+        assert_eq!(s.settings.tags.as_ref().unwrap()["x"], "red");
+        assert_eq!(s.settings.editor, Default::default());
+        s.set_settings_raw("[tags]\nx = \"blue\"\n").unwrap();
+        assert_eq!(s.settings_error(), None);
+    }
+
+    #[test]
+    fn leftover_edits_follow_moved_nodes() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B")]);
+        let b_id = s.get_node(&tp("B")).unwrap().id.clone();
+        let temp_file = s.edit_temp_file(7, &tp("B"), &b_id);
+        assert!(temp_file.starts_with(dir.path().join(FLORG_EDIT_TEMP_DIR)));
+        std::fs::create_dir_all(temp_file.parent().unwrap()).unwrap();
+        std::fs::write(&temp_file, "B\n--\nB changed").unwrap();
+        s.move_node(&tp("B"), &tp("C"), false).unwrap();
+        assert!(temp_file.exists());
+        assert!(s.find_leftover_edits(&[temp_file.clone()]).is_empty());
+
+        let leftovers = s.recover_edits();
+        assert_eq!(leftovers.len(), 1);
+        assert_eq!(leftovers[0].path, "C");
+        assert_eq!(leftovers[0].content, "B changed");
+        assert!(leftovers[0].node_exists);
+        s.resolve_leftover_edit(&leftovers[0].name, LeftoverAction::Apply)
+            .unwrap();
+        assert_eq!(s.get_node(&tp("C")).unwrap().raw, "B changed");
+        assert!(!temp_file.exists());
+    }
+
+    #[test]
+    fn leftover_edits_in_node_folders_from_older_versions() {
+        let (dir, mut s) = storage_with(&[("0", "A"), ("1", "B"), ("1/2", "BC")]);
+        std::fs::write(dir.path().join("0/A.temp.adoc"), "A\n--\nA").unwrap();
+        std::fs::write(dir.path().join("1/2/BC.temp.adoc"), "BC\n--\nBC changed").unwrap();
+        let open = dir.path().join("1/B.temp.adoc");
+        std::fs::write(&open, "B\n--\nstill editing").unwrap();
+        let names: Vec<String> = s
+            .find_leftover_edits(std::slice::from_ref(&open))
+            .into_iter()
+            .map(|l| l.name)
+            .collect();
+        assert_eq!(names, ["0/A.temp.adoc", "1/2/BC.temp.adoc"]);
+        std::fs::remove_file(&open).unwrap();
+
+        //the unchanged one is cleaned up
+        let leftovers = s.recover_edits();
+        assert_eq!(leftovers.len(), 1);
+        assert_eq!(leftovers[0].path, "BC");
+        assert_eq!(leftovers[0].content, "BC changed");
+        assert!(!dir.path().join("0/A.temp.adoc").exists());
+
+        s.resolve_leftover_edit(&leftovers[0].name, LeftoverAction::Apply)
+            .unwrap();
+        assert_eq!(s.get_node(&tp("BC")).unwrap().raw, "BC changed");
+        assert!(!dir.path().join("1/2/BC.temp.adoc").exists());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn human_paths_round_trip(
+            blocks in proptest::collection::vec(
+                proptest::prop_oneof![0u32..26, 26u32..100, proptest::num::u32::ANY],
+                0..8
+            )
+        ) {
+            let path = TreePath::from(blocks);
+            proptest::prop_assert_eq!(TreePath::from_human(&path.to_human()).unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn human_path_errors() {
+        let error = |input: &str| TreePath::from_human(input).unwrap_err().to_string();
+        assert_eq!(tp(" BC "), TreePath::from(vec![1, 2]));
+        assert_eq!(tp("26/27A28"), TreePath::from(vec![26, 27, 0, 28]));
+        for input in ["/A", "A/B", "A26/", "26//27", "26/A"] {
+            assert!(error(input).contains("'/' at position"), "{input}");
+        }
+        assert!(error("A027").contains("number '027' at position 1 has leading zeros"));
+        assert!(error("A99999999999").contains("is too large"));
+        assert!(error("Ab").contains("lowercase 'b' at position 1"));
+        assert!(error("A-B").contains("unexpected '-' at position 1"));
+        assert!(error("BC3").contains("number 3 at position 2 - write it as the letter 'D'"));
+    }
+
+    #[test]
+    fn alias_links_follow_the_alias() {
+        let (dir, mut s) = storage_with(&[
+            ("0", "A\n\n<<florg:@todo>>"),
+            ("1", ":alias: todo\nB"),
+            ("2", ":alias: Todo\nC"),
+        ]);
+        assert_eq!(backlinks(&s, "B"), ["A"]);
+        assert_eq!(s.duplicate_aliases().len(), 1);
+
+        //B gives the alias up - C (the duplicate) gets it, and the link with it
+        s.replace_node(Node::new(&tp("B"), "B"), false).unwrap();
+        assert!(backlinks(&s, "B").is_empty());
+        assert_eq!(backlinks(&s, "C"), ["A"]);
+        assert!(s.duplicate_aliases().is_empty());
+
+        //nobody has it any more
+        s.delete_node(&tp("C"), false).unwrap();
+        assert!(s.resolve_path("@todo").is_err());
+        assert!(s.aliases().is_empty());
+
+        //until a new node declares it
+        s.replace_node(Node::new(&tp("D"), ":alias: todo\nD"), false)
+            .unwrap();
+        assert_eq!(backlinks(&s, "D"), ["A"]);
+
+        //changed on disk - alias and link in the same reload
+        std::fs::create_dir_all(dir.path().join("4")).unwrap();
+        std::fs::write(dir.path().join("4/node.adoc"), ":alias: later\nE").unwrap();
+        std::fs::write(dir.path().join("0/node.adoc"), "A\n\n<<florg:@later>>").unwrap();
+        s.reload_subtree(&TreePath::new());
+        assert_eq!(s.aliases().get("later").map(|p| &p[..]), Some("E"));
+        assert_eq!(backlinks(&s, "E"), ["A"]);
+        assert!(backlinks(&s, "D").is_empty());
+    }
+
+    #[test]
+    fn timestamps_only_when_enabled() {
+        let (_dir, mut s) = storage_with(&[("0", "A")]);
+        s.replace_node(Node::from_raw(tp("B"), String::new(), "B".into()), false)
+            .unwrap();
+        assert_eq!(s.get_node(&tp("B")).unwrap().raw, "B");
+
+        s.settings.properties.timestamps = true;
+        s.replace_node(Node::from_raw(tp("C"), String::new(), "C".into()), false)
+            .unwrap();
+        let c = s.get_node(&tp("C")).unwrap();
+        assert!(c.properties.contains_key("created"));
+        assert!(c.properties.contains_key("modified"));
+        assert_eq!(c.header.title, "C");
+    }
+
+    #[test]
+    fn queued_commits_wait_for_the_storage() {
+        let (_dir, s) = storage_with(&[("0", "A")]);
+        s.add_and_commit("one").unwrap();
+        s.add_and_commit("two").unwrap();
+        //nothing is committed behind the storage's back
+        assert!(s.repository().history(10).unwrap().is_empty());
+        s.commit_queued();
+        let history = s.repository().history(10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].message, "one (+1 more)\n\n- one\n- two");
+        //the queue is empty now
+        s.commit_queued();
+        assert_eq!(s.repository().history(10).unwrap().len(), 1);
+    }
+
+    /// A git repository in a temp dir, with the data path in a sub folder
+    /// (so the prefix handling is exercised as well)
+    fn repository() -> (tempfile::TempDir, PathBuf, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "florg test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let data_path = dir.path().join("data");
+        std::fs::create_dir_all(&data_path).unwrap();
+        let repo = Repository::open(&data_path).unwrap();
+        (dir, data_path, repo)
+    }
+
+    fn write(data_path: &Path, file: &str, content: &str) {
+        let file = data_path.join(file);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, content).unwrap();
+    }
+
+    #[test]
+    fn repository_commits_only_the_data_path() {
+        let (dir, data_path, repo) = repository();
+        assert!(repo.history(10).unwrap().is_empty());
+        write(&data_path, "0/node.adoc", "A");
+        write(&data_path, ".gitignore", "*.cache\n");
+        write(&data_path, "0/node.cache", "cached");
+        std::fs::write(dir.path().join("outside.txt"), "not ours").unwrap();
+        let first = repo.add_all_and_commit("first").unwrap().unwrap();
+        //nothing changed - no empty commit
+        assert_eq!(repo.add_all_and_commit("again").unwrap(), None);
+
+        let commit = repo.find_commit(&first.to_string()).unwrap();
+        let mut files: Vec<PathBuf> = repo
+            .changed_files(&commit)
+            .unwrap()
+            .into_iter()
+            .filter_map(|(_, new)| new)
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [PathBuf::from(".gitignore"), PathBuf::from("0/node.adoc")]
+        );
+
+        std::fs::remove_file(data_path.join("0/node.adoc")).unwrap();
+        repo.add_all_and_commit("removed").unwrap().unwrap();
+        let head = repo.find_commit("HEAD").unwrap();
+        assert_eq!(
+            repo.changed_files(&head).unwrap(),
+            vec![(Some(PathBuf::from("0/node.adoc")), None)]
+        );
+        assert_eq!(repo.file_at(&head, Path::new("0/node.adoc")).unwrap(), None);
+        assert_eq!(
+            repo.file_at(&commit, Path::new("0/node.adoc")).unwrap(),
+            Some("A".to_string())
+        );
+    }
+
+    #[test]
+    fn repository_history_keeps_multi_line_messages() {
+        let (_dir, data_path, repo) = repository();
+        write(&data_path, "0/node.adoc", "A");
+        repo.add_all_and_commit("Added node A\n\nwith a body\nover lines")
+            .unwrap();
+        write(&data_path, "0/node.adoc", "A2");
+        repo.add_all_and_commit("Changed A").unwrap();
+        let history = repo.history(10).unwrap();
+        let messages: Vec<&str> = history.iter().map(|e| &e.message[..]).collect();
+        assert_eq!(
+            messages,
+            ["Changed A", "Added node A\n\nwith a body\nover lines"]
+        );
+        assert_eq!(history[0].hash.len(), 40);
+        assert_eq!(history[0].date.len(), "2026-01-01 10:00".len());
+        assert_eq!(repo.history(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn repository_file_history_follows_renames() {
+        let (_dir, data_path, repo) = repository();
+        let text = "A node with enough text\nso that git detects\nthe rename\n";
+        write(&data_path, "0/node.adoc", text);
+        repo.add_all_and_commit("added").unwrap();
+        write(&data_path, "1/node.adoc", "unrelated");
+        repo.add_all_and_commit("other node").unwrap();
+        std::fs::rename(data_path.join("0"), data_path.join("2")).unwrap();
+        repo.add_all_and_commit("moved").unwrap();
+        write(&data_path, "2/node.adoc", &format!("{text}more\n"));
+        repo.add_all_and_commit("changed").unwrap();
+
+        let history = repo.file_history(Path::new("2/node.adoc"), 10).unwrap();
+        let entries: Vec<(&str, PathBuf)> = history
+            .iter()
+            .map(|(e, file)| (&e.message[..], file.clone()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("changed", PathBuf::from("2/node.adoc")),
+                ("moved", PathBuf::from("2/node.adoc")),
+                ("added", PathBuf::from("0/node.adoc")),
+            ]
+        );
+        assert_eq!(
+            repo.file_history(Path::new("2/node.adoc"), 1)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn repository_revert() {
+        let (_dir, data_path, repo) = repository();
+        write(&data_path, "0/node.adoc", "one\n");
+        repo.add_all_and_commit("one").unwrap();
+        let base = repo.find_commit("HEAD").unwrap().id();
+        write(&data_path, "0/node.adoc", "two\n");
+        repo.add_all_and_commit("two").unwrap();
+        write(&data_path, "1/node.adoc", "B\n");
+        repo.add_all_and_commit("three").unwrap();
+
+        let base = repo.find_commit(&base.to_string()).unwrap();
+        let since = repo.commits_since(&base).unwrap();
+        let summaries: Vec<&str> = since.iter().map(|c| c.summary().unwrap()).collect();
+        assert_eq!(summaries, ["three", "two"]);
+        repo.revert_commits(&since, "Undo to one").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(data_path.join("0/node.adoc")).unwrap(),
+            "one\n"
+        );
+        assert!(!data_path.join("1/node.adoc").exists());
+        assert_eq!(repo.history(1).unwrap()[0].message, "Undo to one");
+        //nothing left to commit - the revert committed the files it changed
+        assert_eq!(repo.add_all_and_commit("nothing").unwrap(), None);
+
+        //reverting 'two' again conflicts with the revert
+        let two = repo.find_commit("HEAD~2").unwrap();
+        write(&data_path, "0/node.adoc", "three\n");
+        repo.add_all_and_commit("changed again").unwrap();
+        let err = repo.revert_commits(&[two], "undo two").unwrap_err();
+        assert!(
+            err.to_string().contains("conflicts with later changes"),
+            "{err}"
+        );
+    }
+
+    /// The BTreeMap against the linear scans of the old Vec<Node>, over 50k nodes.
+    /// cargo test --release big_tree -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn big_tree_benchmark() {
+        let (_dir, mut s) = storage_with(&[]);
+        for a in 0..20u32 {
+            for b in 0..50u32 {
+                for c in 0..49u32 {
+                    let paths = [vec![a], vec![a, b], vec![a, b, c]];
+                    for path in paths.iter().skip(if c == 0 { 0 } else { 2 }) {
+                        let path = TreePath::from(path.clone());
+                        let id = format!("{:08x}", s.nodes.len());
+                        let node = Node::from_raw(path.clone(), id.clone(), format!("{}", path));
+                        s.ids.insert(id, path.clone());
+                        s.nodes.insert(path, node);
+                    }
+                }
+            }
+        }
+        assert_eq!(s.nodes.len(), 20 + 20 * 50 + 20 * 50 * 49);
+        let as_vec: Vec<Node> = s.nodes.values().cloned().collect();
+        let linear_children = |path: &TreePath| -> Vec<&Node> {
+            as_vec
+                .iter()
+                .filter(|n| n.path.len() == path.len() + 1 && n.path.starts_with(path))
+                .collect()
+        };
+
+        //what get_tree does: the children of every node, two levels deep
+        let walk = |children: &dyn Fn(&TreePath) -> Vec<TreePath>| {
+            let start = std::time::Instant::now();
+            let mut count = 0;
+            for top in children(&TreePath::new()) {
+                for child in children(&top) {
+                    count += children(&child).len();
+                }
+            }
+            assert_eq!(count, 20 * 50 * 49);
+            start.elapsed()
+        };
+        let tree_map = walk(&|p| s.children_paths_for(p));
+        let tree_vec = walk(&|p| linear_children(p).iter().map(|n| n.path.clone()).collect());
+
+        let sample: Vec<TreePath> = as_vec.iter().step_by(50).map(|n| n.path.clone()).collect();
+        let start = std::time::Instant::now();
+        for path in sample.iter() {
+            assert!(s.get_node(path).is_some());
+        }
+        let lookup_map = start.elapsed();
+        let start = std::time::Instant::now();
+        for path in sample.iter() {
+            assert!(as_vec.iter().any(|n| &n.path == path));
+        }
+        let lookup_vec = start.elapsed();
+
+        println!("get_tree walk: {:?} (Vec: {:?})", tree_map, tree_vec);
+        println!(
+            "{} lookups: {:?} (Vec: {:?})",
+            sample.len(),
+            lookup_map,
+            lookup_vec
+        );
+        assert!(tree_map * 10 < tree_vec);
+        assert!(lookup_map * 10 < lookup_vec);
+    }
+}
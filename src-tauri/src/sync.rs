@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{sync::Mutex, thread, time::Duration};
+use tauri::Manager;
+
+use crate::storage::{Repository, Storage, TreePath};
+use crate::{RUNTIME_STATE, STORAGE};
+
+// Sync the data path with a remote git repository:
+// commit, fetch, merge (or fast forward), push - and reload if anything came in.
+//
+// Configured in the [sync] section of settings.toml, see settings::SyncSettings.
+
+/// what the last sync did, sent to the frontend as 'sync-status'
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Default)]
+pub(crate) struct SyncStatus {
+    //commits we have that the remote doesn't (after the sync: 0 unless the push failed)
+    pub ahead: usize,
+    //commits the remote has that we don't
+    pub behind: usize,
+    //commits brought in by this sync
+    pub pulled: usize,
+    //commits sent by this sync
+    pub pushed: usize,
+    //nodes (human paths) with conflict markers after merging
+    pub conflicts: Vec<String>,
+    pub last_sync: String,
+    pub error: Option<String>,
+}
+
+/// Sync with the remote. The storage is only locked while the working tree
+/// and the nodes change (commit, merge, reload) - fetch and push run without it,
+/// on a repository handle of their own, as they only touch refs.
+pub(crate) fn sync(storage: &Mutex<Storage>) -> Result<SyncStatus> {
+    let (settings, data_path, branch) = {
+        let ss = storage.lock().unwrap();
+        let settings = ss
+            .settings
+            .sync
+            .clone()
+            .context("No [sync] section in settings.toml")?;
+        let repo = ss.repository();
+        repo.ensure_remote(&settings.remote, settings.url.as_deref())?;
+        (settings, ss.data_path.clone(), repo.current_branch()?)
+    };
+    let remote_branch = settings.branch.clone().unwrap_or_else(|| branch.clone());
+    let mut status = SyncStatus {
+        last_sync: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        ..Default::default()
+    };
+    let network = Repository::open(&data_path)?;
+    network.fetch(&settings.remote, &remote_branch)?;
+
+    let conflicts = {
+        let mut ss = storage.lock().unwrap();
+        ss.flush_commits()?;
+        let repo = ss.repository();
+        let (_, behind) = repo.ahead_behind(&settings.remote, &remote_branch)?;
+        status.pulled = behind;
+        let conflicts = repo.merge_fetched(&settings.remote, &remote_branch)?;
+        if status.pulled > 0 {
+            ss.reload();
+        }
+        conflicts
+    };
+
+    let (ahead, _) = network.ahead_behind(&settings.remote, &remote_branch)?;
+    if ahead > 0 {
+        network.push(&settings.remote, &branch, &remote_branch)?;
+        status.pushed = ahead;
+    }
+
+    {
+        let ss = storage.lock().unwrap();
+        let (ahead, behind) = ss
+            .repository()
+            .ahead_behind(&settings.remote, &remote_branch)?;
+        status.ahead = ahead;
+        status.behind = behind;
+    }
+    status.conflicts = conflicts
+        .iter()
+        .filter_map(|file| TreePath::from_file_path(&file.parent()?.to_string_lossy()).ok())
+        .map(|path| path.to_human())
+        .collect();
+    Ok(status)
+}
+
+fn emit_status(status: &SyncStatus) {
+    if let Some(rt) = RUNTIME_STATE.get() {
+        let lock = rt.lock().unwrap();
+        lock.app_handle.emit_all("sync-status", status).ok();
+        if status.pulled > 0 {
+            lock.app_handle.emit_all("node-changed", "").ok();
+        }
+    }
+}
+
+/// sync and tell the frontend about it - errors end up in the status as well
+pub(crate) fn sync_and_report() -> Result<SyncStatus> {
+    let res = sync(STORAGE.get().unwrap());
+    match &res {
+        Ok(status) => emit_status(status),
+        Err(e) => emit_status(&SyncStatus {
+            last_sync: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+            error: Some(format!("{:?}", e)),
+            ..Default::default()
+        }),
+    }
+    res
+}
+
+/// sync every sync.interval_minutes - re-read from the settings each round,
+/// so changing them needs no restart
+pub(crate) fn spawn_periodic_sync() {
+    thread::spawn(|| loop {
+        let interval = {
+            let ss = STORAGE.get().unwrap().lock().unwrap();
+            ss.settings.sync.as_ref().and_then(|s| s.interval())
+        };
+        match interval {
+            Some(interval) => {
+                thread::sleep(interval);
+                //failures reach the frontend through the sync-status event
+                sync_and_report().ok();
+            }
+            None => thread::sleep(Duration::from_secs(60)),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SyncSettings;
+    use crate::storage::Node;
+
+    /// a storage on a fresh clone of the bare repository at url
+    fn clone(url: &str) -> (tempfile::TempDir, Mutex<Storage>) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::clone(url, dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "florg test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        //the settings stay local, so they survive the reloads but don't get merged
+        std::fs::write(dir.path().join(".git/info/exclude"), "settings.toml\n").unwrap();
+        std::fs::write(dir.path().join("settings.toml"), "[sync]\n").unwrap();
+        let storage = Storage::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(storage.settings.sync, Some(SyncSettings::default()));
+        (dir, Mutex::new(storage))
+    }
+
+    fn tp(human: &str) -> TreePath {
+        TreePath::from_human(human).unwrap()
+    }
+
+    fn change(storage: &Mutex<Storage>, path: &str, raw: &str) {
+        storage
+            .lock()
+            .unwrap()
+            .replace_node(Node::new(&tp(path), raw), true)
+            .unwrap();
+    }
+
+    fn raw(storage: &Mutex<Storage>, path: &str) -> String {
+        let ss = storage.lock().unwrap();
+        ss.get_node(&tp(path)).unwrap().raw.clone()
+    }
+
+    #[test]
+    fn sync_two_clones() {
+        let bare = tempfile::tempdir().unwrap();
+        git2::Repository::init_bare(bare.path()).unwrap();
+        let url = bare.path().to_string_lossy().to_string();
+        let (_a_dir, a) = clone(&url);
+        let (_b_dir, b) = clone(&url);
+
+        change(&a, "A", "A v1");
+        change(&a, "B", "B v1");
+        let status = sync(&a).unwrap();
+        assert_eq!(
+            (status.pulled, status.pushed, status.ahead, status.behind),
+            (0, 1, 0, 0)
+        );
+
+        //b has nothing committed yet and just takes what is there
+        let status = sync(&b).unwrap();
+        assert_eq!(
+            (status.pulled, status.pushed, status.ahead, status.behind),
+            (1, 0, 0, 0)
+        );
+        assert_eq!(raw(&b, "A"), "A v1");
+
+        //changes to different nodes on both sides: b merges and pushes the merge
+        change(&a, "A", "A v2");
+        change(&a, "A", "A v3");
+        change(&b, "B", "B v2");
+        assert_eq!(sync(&a).unwrap().pushed, 1);
+        let status = sync(&b).unwrap();
+        assert_eq!(
+            (status.pulled, status.pushed, status.ahead, status.behind),
+            (1, 2, 0, 0)
+        );
+        assert!(status.conflicts.is_empty());
+        assert_eq!(raw(&b, "A"), "A v3");
+        assert_eq!(raw(&b, "B"), "B v2");
+
+        //a fast forwards to the merge
+        let status = sync(&a).unwrap();
+        assert_eq!(
+            (status.pulled, status.pushed, status.ahead, status.behind),
+            (2, 0, 0, 0)
+        );
+        assert_eq!(raw(&a, "B"), "B v2");
+
+        //the same node changed on both sides
+        change(&a, "A", "A from a");
+        change(&b, "A", "A from b");
+        sync(&a).unwrap();
+        let status = sync(&b).unwrap();
+        assert_eq!(status.conflicts, vec!["A".to_string()]);
+        assert!(raw(&b, "A").contains("<<<<<<<"));
+        assert_eq!((status.ahead, status.behind), (0, 0));
+
+        //an unreachable remote is an error, not a silent no-op
+        a.lock().unwrap().settings.sync = Some(SyncSettings {
+            url: Some(bare.path().join("missing").to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        assert!(sync(&a).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{Context, Result};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use tauri::Manager;
+
+use crate::storage::{Node, TreePath, FLORG_FILENAME};
+use crate::{RUNTIME_STATE, STORAGE};
+
+//how long to wait for more events before touching the storage,
+//so a git pull / checkout is handled as one batch
+const DEBOUNCE_MS: u64 = 250;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    Node(TreePath),
+    Subtree(TreePath),
+}
+
+impl Change {
+    fn path(&self) -> &TreePath {
+        match self {
+            Change::Node(p) => p,
+            Change::Subtree(p) => p,
+        }
+    }
+}
+
+struct RawEvent {
+    wd: WatchDescriptor,
+    mask: EventMask,
+    name: Option<OsString>,
+}
+
+/// Watch the data_path for changes made outside of florg
+/// (git pull, another editor, scripts), update the affected
+/// nodes in the storage and tell the frontend via 'node-changed'.
+pub(crate) fn spawn_data_watcher(data_path: PathBuf) -> Result<()> {
+    let mut inotify = Inotify::init().context("inotify init failed")?;
+    let mut watches = HashMap::new();
+    add_watches_below(&mut inotify, &mut watches, &data_path, &TreePath::new());
+    thread::spawn(move || watch_loop(inotify, watches, data_path));
+    Ok(())
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+}
+
+//only the node folders (numbers) are of interest -
+//this skips .git, temp, history, chatgpt...
+fn parse_node_folder_name(name: &std::ffi::OsStr) -> Option<u32> {
+    name.to_str()?.parse::<u32>().ok()
+}
+
+fn add_watches_below(
+    inotify: &mut Inotify,
+    watches: &mut HashMap<WatchDescriptor, TreePath>,
+    dir: &Path,
+    path: &TreePath,
+) {
+    //adding a watch for an inode we already watch returns the same descriptor,
+    //so this also fixes up the paths of moved folders
+    match inotify.watches().add(dir, watch_mask()) {
+        Ok(wd) => {
+            watches.insert(wd, path.clone());
+        }
+        Err(e) => {
+            println!("failed to watch {:?}: {}", dir, e);
+            return;
+        }
+    }
+    if let Ok(rd) = std::fs::read_dir(dir) {
+        for entry in rd.filter_map(|x| x.ok()) {
+            if !entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
+                continue;
+            }
+            if let Some(no) = parse_node_folder_name(&entry.file_name()) {
+                add_watches_below(inotify, watches, &entry.path(), &path.append(no));
+            }
+        }
+    }
+}
+
+/// Forget the folders at and below path - they were deleted or moved away
+/// (e.g. into .trash, where a watch would keep reporting them under their old path).
+/// A move within the data path re-adds them on MOVED_TO.
+fn remove_watches_below(
+    inotify: &mut Inotify,
+    watches: &mut HashMap<WatchDescriptor, TreePath>,
+    path: &TreePath,
+) {
+    let gone: Vec<WatchDescriptor> = watches
+        .iter()
+        .filter(|(_, p)| p.starts_with(path))
+        .map(|(wd, _)| wd.clone())
+        .collect();
+    for wd in gone {
+        watches.remove(&wd);
+        //fails if the folder was deleted - the watch is gone already
+        inotify.watches().remove(wd).ok();
+    }
+}
+
+fn read_events(inotify: &mut Inotify, buffer: &mut [u8], blocking: bool) -> Vec<RawEvent> {
+    let events = if blocking {
+        inotify.read_events_blocking(buffer)
+    } else {
+        inotify.read_events(buffer)
+    };
+    match events {
+        Ok(events) => events
+            .map(|e| RawEvent {
+                wd: e.wd,
+                mask: e.mask,
+                name: e.name.map(|x| x.to_os_string()),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn watch_loop(
+    mut inotify: Inotify,
+    mut watches: HashMap<WatchDescriptor, TreePath>,
+    data_path: PathBuf,
+) {
+    let mut buffer = [0; 4096];
+    loop {
+        let mut events = read_events(&mut inotify, &mut buffer, true);
+        thread::sleep(Duration::from_millis(DEBOUNCE_MS));
+        loop {
+            let more = read_events(&mut inotify, &mut buffer, false);
+            if more.is_empty() {
+                break;
+            }
+            events.extend(more);
+        }
+
+        let mut changes = Vec::new();
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                //we lost events - play it safe
+                changes.push(Change::Subtree(TreePath::new()));
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                watches.remove(&event.wd);
+                continue;
+            }
+            let dir_path = match watches.get(&event.wd) {
+                Some(p) => p.clone(),
+                None => continue,
+            };
+            let name = match event.name {
+                Some(name) => name,
+                None => continue,
+            };
+            if event.mask.contains(EventMask::ISDIR) {
+                if let Some(no) = parse_node_folder_name(&name) {
+                    let child = dir_path.append(no);
+                    if event
+                        .mask
+                        .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                    {
+                        let dir = Node::dirname_from_path(&data_path, &child);
+                        add_watches_below(&mut inotify, &mut watches, &dir, &child);
+                    } else if event
+                        .mask
+                        .intersects(EventMask::DELETE | EventMask::MOVED_FROM)
+                    {
+                        remove_watches_below(&mut inotify, &mut watches, &child);
+                    }
+                    changes.push(Change::Subtree(child));
+                }
+            } else if name == FLORG_FILENAME {
+                changes.push(Change::Node(dir_path));
+            }
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        apply_changes(changes);
+    }
+}
+
+fn apply_changes(mut changes: Vec<Change>) {
+    changes.sort();
+    changes.dedup();
+    let subtrees: Vec<TreePath> = changes
+        .iter()
+        .filter_map(|c| match c {
+            Change::Subtree(p) => Some(p.clone()),
+            _ => None,
+        })
+        .collect();
+    //anything inside a reloaded subtree is covered by it
+    changes.retain(|c| {
+        !subtrees
+            .iter()
+            .any(|s| c.path().starts_with(s) && c != &Change::Subtree(s.clone()))
+    });
+
+    let mut changed = Vec::new();
+    {
+        let mut ss = STORAGE.get().unwrap().lock().unwrap();
+        for change in changes.iter() {
+            changed.extend(match change {
+                Change::Node(p) => ss.reload_node(p),
+                Change::Subtree(p) => ss.reload_subtree(p),
+            });
+        }
+    }
+    if changed.is_empty() {
+        return;
+    }
+    if let Some(rt) = RUNTIME_STATE.get() {
+        let lock = rt.lock().unwrap();
+        for path in changed {
+            lock.app_handle
+                .emit_all("node-changed", path.to_human())
+                .ok();
+        }
+    }
+}